@@ -0,0 +1,138 @@
+// Explicit returns are used throughout this crate for readability.
+#![allow(clippy::needless_return)]
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+#[allow(dead_code)]
+#[path = "../src/edit_distance.rs"]
+mod edit_distance;
+
+#[path = "../src/xorshift.rs"]
+mod xorshift;
+
+use edit_distance::{bounded_distance, tokenize};
+use xorshift::Rng;
+
+/// Words weighted roughly the way they show up in TV dialogue
+#[rustfmt::skip]
+const VOCABULARY: &[&str] = &[
+	"you", "i", "the", "to", "a", "it", "and", "that", "what", "is", "me", "we", "this", "of",
+	"don't", "in", "know", "no", "be", "have", "my", "your", "just", "do", "not", "for", "was",
+	"on", "it's", "get", "i'm", "with", "are", "all", "right", "can", "here", "there", "yeah",
+	"oh", "so", "go", "like", "he", "she", "about", "okay", "think", "want", "well", "out",
+	"up", "how", "now", "come", "they", "but", "if", "gonna", "one", "why", "who", "him",
+	"her", "did", "where", "look", "at", "back", "got", "good", "tell", "see", "let's",
+	"really", "mean", "sorry", "time", "never", "need", "take", "going", "something",
+	"thank", "hey", "please", "because", "sure", "us", "little", "love", "believe", "talk",
+	"maybe", "thing", "doctor", "captain", "ship", "police", "money", "house", "tonight",
+	"tomorrow", "father", "mother", "brother", "sister", "kill", "dead", "help", "wait",
+	"stop", "listen", "remember", "understand", "happened", "everything", "nothing",
+	"someone", "nobody", "alright", "morning", "Jack", "Sarah", "Michael", "Lisa", "Carter",
+];
+
+/// Builds a stripped transcript of roughly `bytes` bytes, as `strip_subtitles` would
+fn transcript(seed: u64, bytes: usize) -> String {
+	let mut rng = Rng(seed);
+	let mut text = String::new();
+	while text.len() < bytes {
+		let sentence_length = 3 + rng.below(9);
+		for idx in 0..sentence_length {
+			// Squaring skews the distribution toward the common words at the front
+			let roll = rng.below(1000) as f64 / 1000.0;
+			let word = VOCABULARY[(roll * roll * VOCABULARY.len() as f64) as usize];
+			if idx > 0 {
+				text.push(' ');
+			}
+			text.push_str(word);
+		}
+		text.push_str(["? ", ". ", "! ", ", "][rng.below(4)]);
+	}
+	return text;
+}
+
+/// Simulates OCR output of the same episode: misread letters, split words, lost lines
+fn ocr_copy(text: &str, seed: u64) -> String {
+	let mut rng = Rng(seed);
+	let mut copy = String::new();
+	for word in text.split(' ') {
+		match rng.below(100) {
+			0..=2 => continue,
+			3..=7 => copy.push_str(&word.replace('l', "I").replace('m', "rn")),
+			8..=9 if word.len() > 3 => {
+				copy.push_str(&word[..2]);
+				copy.push(' ');
+				copy.push_str(&word[2..]);
+			}
+			_ => copy.push_str(word),
+		}
+		copy.push(' ');
+	}
+	return copy;
+}
+
+fn bench_distances(c: &mut Criterion) {
+	let reference = transcript(0x5eed, 40_000);
+	let ripped = ocr_copy(&reference, 0xfeed);
+	let unrelated = transcript(0xbeef, 40_000);
+
+	let reference_tokens = tokenize(&reference);
+	let ripped_tokens = tokenize(&ripped);
+	let unrelated_tokens = tokenize(&unrelated);
+	let matching_distance =
+		bounded_distance(&reference_tokens, &ripped_tokens, usize::MAX).unwrap();
+
+	let mut group = c.benchmark_group("edit_distance");
+	group.sample_size(10);
+
+	group.bench_function("strsim_levenshtein/matching", |b| {
+		b.iter(|| strsim::levenshtein(black_box(&reference), black_box(&ripped)))
+	});
+	group.bench_function("strsim_levenshtein/unrelated", |b| {
+		b.iter(|| strsim::levenshtein(black_box(&reference), black_box(&unrelated)))
+	});
+
+	group.bench_function("tokenize", |b| b.iter(|| tokenize(black_box(&reference))));
+
+	group.bench_function("bit_parallel/matching", |b| {
+		b.iter(|| {
+			bounded_distance(
+				black_box(&reference_tokens),
+				black_box(&ripped_tokens),
+				usize::MAX,
+			)
+		})
+	});
+	group.bench_function("bit_parallel/unrelated", |b| {
+		b.iter(|| {
+			bounded_distance(
+				black_box(&reference_tokens),
+				black_box(&unrelated_tokens),
+				usize::MAX,
+			)
+		})
+	});
+	// The unrelated episode against the bound set by the correct one, which is what
+	// happens to every candidate after the best one in `tag_items`
+	group.bench_function("bit_parallel/unrelated_bounded", |b| {
+		b.iter(|| {
+			bounded_distance(
+				black_box(&reference_tokens),
+				black_box(&unrelated_tokens),
+				matching_distance,
+			)
+		})
+	});
+	group.bench_function("bit_parallel/matching_bounded", |b| {
+		b.iter(|| {
+			bounded_distance(
+				black_box(&reference_tokens),
+				black_box(&ripped_tokens),
+				matching_distance,
+			)
+		})
+	});
+	group.finish();
+}
+
+criterion_group!(benches, bench_distances);
+criterion_main!(benches);
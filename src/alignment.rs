@@ -0,0 +1,376 @@
+use lazy_regex::regex;
+
+/// Framerate ratios commonly seen between a disc and a downloaded subtitle file.
+/// PAL releases are sped up from 23.976 to 25fps, and some subtitles are timed
+/// against 24fps masters.
+const DRIFT_CANDIDATES: [f64; 5] = [
+	1.0,
+	25.0 / 23.976,
+	23.976 / 25.0,
+	24.0 / 23.976,
+	23.976 / 24.0,
+];
+
+/// Largest constant offset (in milliseconds) considered between two subtitle files.
+/// Different intros, recaps and studio logos rarely shift things more than this.
+const MAX_OFFSET_MS: i64 = 300_000;
+
+/// Width of the histogram bins used to vote on the offset
+const OFFSET_BIN_MS: i64 = 100;
+
+/// How far apart two cue start times may be while still being considered the same line
+const CUE_TOLERANCE_MS: f64 = 300.0;
+
+/// A single subtitle entry with its display times in milliseconds
+#[derive(Debug, Clone)]
+pub struct Cue {
+	pub start: u32,
+	pub end: u32,
+	pub text: String,
+}
+
+/// Describes how one set of cues lines up against another.
+///
+/// Ripped times map onto reference times as `reference = drift * ripped + offset`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TimingAlignment {
+	pub drift: f64,
+	pub offset_ms: f64,
+	pub matched: usize,
+	/// Fraction of cues that line up between both files, from 0 to 1.
+	pub score: f64,
+}
+
+/// Parses the cues out of an srt file, ignoring any malformed entries
+pub fn parse_srt(contents: &str) -> Vec<Cue> {
+	let timing = regex!(
+		r"(\d+):(\d{1,2}):(\d{1,2})[,.](\d{1,3})\s*-->\s*(\d+):(\d{1,2}):(\d{1,2})[,.](\d{1,3})"
+	);
+	let mut cues = Vec::<Cue>::new();
+	let mut current: Option<Cue> = None;
+	for line in contents.lines() {
+		let line = line.trim_matches(|c: char| c == '\u{feff}' || c.is_whitespace());
+		if let Some(captures) = timing.captures(line) {
+			if let Some(cue) = current.take() {
+				cues.push(cue);
+			}
+			let time = |offset: usize| -> Option<u32> {
+				let field = |idx: usize| captures[offset + idx].parse::<u64>().ok();
+				let millis = &captures[offset + 3];
+				// "1,5" means 500ms, not 5ms
+				let millis = millis.parse::<u64>().ok()? * 10u64.pow(3 - millis.len() as u32);
+				let seconds = field(0)?
+					.checked_mul(3600)?
+					.checked_add(field(1)? * 60 + field(2)?)?;
+				return u32::try_from(seconds.checked_mul(1000)?.checked_add(millis)?).ok();
+			};
+			// Times too large to fit are from a damaged file, so the cue is left out
+			current = match (time(1), time(5)) {
+				(Some(start), Some(end)) => Some(Cue {
+					start,
+					end,
+					text: String::new(),
+				}),
+				_ => None,
+			};
+		} else if line.is_empty() {
+			if let Some(cue) = current.take() {
+				cues.push(cue);
+			}
+		} else if let Some(ref mut cue) = current {
+			if !cue.text.is_empty() {
+				cue.text.push('\n');
+			}
+			cue.text.push_str(line);
+		}
+	}
+	if let Some(cue) = current {
+		cues.push(cue);
+	}
+	cues.sort_by_key(|cue| cue.start);
+	return cues;
+}
+
+/// Writes cues out as an srt file, numbering them in order
+pub fn write_srt(cues: &[Cue]) -> String {
+	let time = |millis: u32| -> String {
+		let seconds = millis / 1000;
+		return format!(
+			"{:02}:{:02}:{:02},{:03}",
+			seconds / 3600,
+			seconds / 60 % 60,
+			seconds % 60,
+			millis % 1000
+		);
+	};
+	let mut srt = String::new();
+	for (idx, cue) in cues.iter().enumerate() {
+		srt.push_str(&format!(
+			"{}\n{} --> {}\n{}\n\n",
+			idx + 1,
+			time(cue.start),
+			time(cue.end),
+			cue.text
+		));
+	}
+	return srt;
+}
+
+/// Scores how well the rhythm of `ripped` lines up with `reference`, allowing for a
+/// constant offset and a framerate difference between the two.
+///
+/// The text of the cues is not considered at all, which makes this useful for noisy
+/// OCR output where the words are unreliable but the timing is still intact.
+pub fn align_cues(reference: &[Cue], ripped: &[Cue]) -> TimingAlignment {
+	if reference.is_empty() || ripped.is_empty() {
+		return TimingAlignment::default();
+	}
+	let reference_starts: Vec<f64> = reference.iter().map(|cue| cue.start as f64).collect();
+	let ripped_starts: Vec<f64> = ripped.iter().map(|cue| cue.start as f64).collect();
+
+	let mut best = TimingAlignment::default();
+	for drift in DRIFT_CANDIDATES {
+		let offset = vote_offset(&reference_starts, &ripped_starts, drift);
+		let pairs = match_starts(&reference_starts, &ripped_starts, drift, offset);
+
+		// Refine the guess using the cues we were able to pair up, then try again.
+		// A fit that wanders far from the candidate is just noise from a bad guess.
+		let points: Vec<(f64, f64)> = pairs
+			.iter()
+			.map(|&(reference_idx, ripped_idx)| {
+				(ripped_starts[ripped_idx], reference_starts[reference_idx])
+			})
+			.collect();
+		let (drift, offset) = fit_line(&points)
+			.filter(|fitted| (fitted.0 / drift - 1.0).abs() < 0.005)
+			.unwrap_or((drift, offset));
+		let pairs = match_starts(&reference_starts, &ripped_starts, drift, offset);
+
+		// Lining up the start of a cue earns half of the credit, and the rest depends
+		// on how much the two cues overlap once they're lined up.
+		let credit: f64 = pairs
+			.iter()
+			.map(|&(reference_idx, ripped_idx)| {
+				let reference = &reference[reference_idx];
+				let ripped = &ripped[ripped_idx];
+				let ripped_start = ripped.start as f64 * drift + offset;
+				let ripped_end = ripped.end as f64 * drift + offset;
+				let overlap =
+					ripped_end.min(reference.end as f64) - ripped_start.max(reference.start as f64);
+				let union =
+					ripped_end.max(reference.end as f64) - ripped_start.min(reference.start as f64);
+				let overlap = if union > 0.0 {
+					(overlap / union).max(0.0)
+				} else {
+					1.0
+				};
+				0.5 + 0.5 * overlap
+			})
+			.sum();
+
+		let score = 2.0 * credit / (reference.len() + ripped.len()) as f64;
+		if score > best.score {
+			best = TimingAlignment {
+				drift,
+				offset_ms: offset,
+				matched: pairs.len(),
+				score,
+			};
+		}
+	}
+	return best;
+}
+
+/// Finds the most popular offset between any two cue starts for a given drift
+fn vote_offset(reference: &[f64], ripped: &[f64], drift: f64) -> f64 {
+	let bin_count = (2 * MAX_OFFSET_MS / OFFSET_BIN_MS + 1) as usize;
+	let mut bins = vec![0u32; bin_count];
+	let max_offset = MAX_OFFSET_MS as f64;
+	let mut window_start = 0;
+	for ripped_start in ripped {
+		let scaled = ripped_start * drift;
+		while window_start < reference.len() && reference[window_start] < scaled - max_offset {
+			window_start += 1;
+		}
+		for reference_start in &reference[window_start..] {
+			let offset = reference_start - scaled;
+			if offset > max_offset {
+				break;
+			}
+			let bin = ((offset + max_offset) / OFFSET_BIN_MS as f64) as usize;
+			bins[bin.min(bin_count - 1)] += 1;
+		}
+	}
+
+	// Smooth over neighbouring bins so offsets near a bin boundary aren't split in two
+	let mut best_bin = 0;
+	let mut best_votes = 0;
+	for bin in 0..bin_count {
+		let votes = bins[bin.saturating_sub(1)..(bin + 2).min(bin_count)]
+			.iter()
+			.sum::<u32>();
+		if votes > best_votes {
+			best_votes = votes;
+			best_bin = bin;
+		}
+	}
+	return (best_bin as f64 + 0.5) * OFFSET_BIN_MS as f64 - max_offset;
+}
+
+/// Pairs up cue start times that land on top of each other after applying the
+/// given transformation, returning `(reference, ripped)` index pairs. Both lists
+/// must be sorted, and pairs are kept in order.
+fn match_starts(reference: &[f64], ripped: &[f64], drift: f64, offset: f64) -> Vec<(usize, usize)> {
+	let mut pairs = Vec::<(usize, usize)>::new();
+	let mut next_reference = 0;
+	for (ripped_idx, ripped_start) in ripped.iter().enumerate() {
+		let expected = ripped_start * drift + offset;
+		while next_reference < reference.len()
+			&& reference[next_reference] < expected - CUE_TOLERANCE_MS
+		{
+			next_reference += 1;
+		}
+		if next_reference == reference.len() {
+			break;
+		}
+		if (reference[next_reference] - expected).abs() <= CUE_TOLERANCE_MS {
+			pairs.push((next_reference, ripped_idx));
+			next_reference += 1;
+		}
+	}
+	return pairs;
+}
+
+/// Least-squares fit of `reference = drift * ripped + offset`
+fn fit_line(pairs: &[(f64, f64)]) -> Option<(f64, f64)> {
+	if pairs.len() < 2 {
+		return None;
+	}
+	let count = pairs.len() as f64;
+	let mean_x = pairs.iter().map(|pair| pair.0).sum::<f64>() / count;
+	let mean_y = pairs.iter().map(|pair| pair.1).sum::<f64>() / count;
+	let mut covariance = 0.0;
+	let mut variance = 0.0;
+	for (x, y) in pairs {
+		covariance += (x - mean_x) * (y - mean_y);
+		variance += (x - mean_x) * (x - mean_x);
+	}
+	if variance == 0.0 {
+		return None;
+	}
+	let drift = covariance / variance;
+	return Some((drift, mean_y - drift * mean_x));
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Cues at uneven intervals, like dialogue, so that no offset but the right one
+	/// lines them all up
+	fn dialogue(count: usize) -> Vec<Cue> {
+		let mut start = 60_000;
+		return (0..count)
+			.map(|idx| {
+				start += 1500 + (idx as u32 * 7919) % 4000;
+				Cue {
+					start,
+					end: start + 1200 + (idx as u32 * 104_729) % 1500,
+					text: format!("Line {idx}"),
+				}
+			})
+			.collect();
+	}
+
+	/// The cues as they'd be timed on a disc where `reference = drift * ripped + offset`
+	fn retimed(cues: &[Cue], drift: f64, offset_ms: f64) -> Vec<Cue> {
+		let time = |time: u32| ((time as f64 - offset_ms) / drift).round() as u32;
+		return cues
+			.iter()
+			.map(|cue| Cue {
+				start: time(cue.start),
+				end: time(cue.end),
+				text: cue.text.clone(),
+			})
+			.collect();
+	}
+
+	#[test]
+	fn cues_with_times_that_overflow_are_left_out() {
+		let cues = parse_srt(concat!(
+			"1\n00:00:01,000 --> 00:00:02,000\nFirst\n\n",
+			"2\n999999:00:00,000 --> 999999:00:01,000\nDamaged\n\n",
+			"3\n1200:00:00,000 --> 1200:00:01,000\nToo far\n\n",
+			"4\n00:00:03,000 --> 00:00:04,000\nLast\n",
+		));
+		let texts: Vec<&str> = cues.iter().map(|cue| cue.text.as_str()).collect();
+		assert_eq!(texts, ["First", "Last"]);
+	}
+
+	#[test]
+	fn short_millisecond_fields_are_fractions_of_a_second() {
+		let cues = parse_srt("1\n00:00:01,5 --> 00:00:02.05\nHello\n");
+		assert_eq!((cues[0].start, cues[0].end), (1500, 2050));
+	}
+
+	#[test]
+	fn written_cues_parse_back_the_same() {
+		let cues = dialogue(20);
+		let parsed = parse_srt(&write_srt(&cues));
+		assert_eq!(parsed.len(), cues.len());
+		for (parsed, cue) in parsed.iter().zip(&cues) {
+			assert_eq!((parsed.start, parsed.end), (cue.start, cue.end));
+			assert_eq!(parsed.text, cue.text);
+		}
+	}
+
+	#[test]
+	fn votes_for_a_constant_offset() {
+		let reference = dialogue(100);
+		let ripped = retimed(&reference, 1.0, 4321.0);
+		let starts =
+			|cues: &[Cue]| -> Vec<f64> { cues.iter().map(|cue| cue.start as f64).collect() };
+		let offset = vote_offset(&starts(&reference), &starts(&ripped), 1.0);
+		assert!((offset - 4321.0).abs() <= OFFSET_BIN_MS as f64, "{offset}");
+	}
+
+	#[test]
+	fn recovers_a_constant_offset() {
+		let reference = dialogue(100);
+		let ripped = retimed(&reference, 1.0, -12_345.0);
+		let alignment = align_cues(&reference, &ripped);
+		assert_eq!(alignment.drift, 1.0);
+		assert!(
+			(alignment.offset_ms + 12_345.0).abs() < 50.0,
+			"{alignment:?}"
+		);
+		assert_eq!(alignment.matched, reference.len());
+		assert!(alignment.score > 0.95, "{alignment:?}");
+	}
+
+	#[test]
+	fn recovers_a_framerate_drift() {
+		// A PAL disc plays 4% fast, so its subtitles come sooner and sooner
+		let reference = dialogue(300);
+		let ripped = retimed(&reference, 25.0 / 23.976, 2000.0);
+		let alignment = align_cues(&reference, &ripped);
+		assert!(
+			(alignment.drift - 25.0 / 23.976).abs() < 1e-4,
+			"{alignment:?}"
+		);
+		assert!((alignment.offset_ms - 2000.0).abs() < 50.0, "{alignment:?}");
+		assert_eq!(alignment.matched, reference.len());
+		assert!(alignment.score > 0.95, "{alignment:?}");
+	}
+
+	#[test]
+	fn unrelated_timings_score_low() {
+		let reference = dialogue(100);
+		let mut other = retimed(&dialogue(130)[30..], 1.0, 0.0);
+		for cue in &mut other {
+			cue.start = cue.start * 3 / 2;
+			cue.end = cue.start + 1000;
+		}
+		assert!(align_cues(&reference, &other).score < 0.5);
+	}
+}
@@ -0,0 +1,85 @@
+/// Finds the cheapest way to pair up rows with columns of `costs`, where every row
+/// gets at most one column and every column goes to at most one row.
+///
+/// Any row may also be left unassigned at a cost of `unassigned_cost`, which is how
+/// extras and other titles that don't belong to any episode fall out of the result.
+/// Returns the chosen column for every row.
+pub fn assign(costs: &[Vec<f64>], unassigned_cost: f64) -> Vec<Option<usize>> {
+	let rows = costs.len();
+	if rows == 0 {
+		return Vec::new();
+	}
+	let columns = costs[0].len();
+
+	// Each row gets its own "unassigned" column to fall back on, which also
+	// guarantees there are at least as many columns as rows.
+	let width = columns + rows;
+	let cost = |row: usize, column: usize| -> f64 {
+		if column < columns {
+			return costs[row][column];
+		} else {
+			return unassigned_cost;
+		}
+	};
+
+	// Hungarian algorithm using potentials. Rows and columns are 1-indexed here, with
+	// index 0 used as a sentinel for the row currently being inserted.
+	let mut row_potential = vec![0.0; rows + 1];
+	let mut column_potential = vec![0.0; width + 1];
+	let mut column_owner = vec![0usize; width + 1];
+	let mut previous = vec![0usize; width + 1];
+	for row in 1..=rows {
+		column_owner[0] = row;
+		let mut current_column = 0;
+		let mut min_slack = vec![f64::INFINITY; width + 1];
+		let mut used = vec![false; width + 1];
+		loop {
+			used[current_column] = true;
+			let current_row = column_owner[current_column];
+			let mut delta = f64::INFINITY;
+			let mut next_column = 0;
+			for column in 1..=width {
+				if used[column] {
+					continue;
+				}
+				let slack = cost(current_row - 1, column - 1)
+					- row_potential[current_row]
+					- column_potential[column];
+				if slack < min_slack[column] {
+					min_slack[column] = slack;
+					previous[column] = current_column;
+				}
+				if min_slack[column] < delta {
+					delta = min_slack[column];
+					next_column = column;
+				}
+			}
+			for column in 0..=width {
+				if used[column] {
+					row_potential[column_owner[column]] += delta;
+					column_potential[column] -= delta;
+				} else {
+					min_slack[column] -= delta;
+				}
+			}
+			current_column = next_column;
+			if column_owner[current_column] == 0 {
+				break;
+			}
+		}
+		// Walk the augmenting path back to the sentinel
+		while current_column != 0 {
+			let previous_column = previous[current_column];
+			column_owner[current_column] = column_owner[previous_column];
+			current_column = previous_column;
+		}
+	}
+
+	let mut assignments = vec![None; rows];
+	for column in 1..=columns {
+		if column_owner[column] != 0 {
+			assignments[column_owner[column] - 1] = Some(column - 1);
+		}
+	}
+	return assignments;
+}
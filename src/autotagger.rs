@@ -1,52 +1,322 @@
 use std::{
-	collections::HashMap,
+	collections::{HashMap, HashSet},
 	path::{Path, PathBuf},
+	time::Duration,
 };
 
-use anyhow::Context;
+use anyhow::{anyhow, Context};
+use clap::Args;
 use dialoguer::{Confirm, MultiSelect, Select};
-use lazy_regex::regex;
-use tmdb_async::{Episode, TV};
+use tmdb_async::{Episode, EpisodeGroup, EpisodeSubgroup, TV};
 use tokio::{
 	fs::{self, File},
 	io::AsyncReadExt,
 	sync::mpsc,
-	task,
 };
 use urlencoding::encode;
 
 use crate::{
+	alignment::{align_cues, parse_srt, Cue, TimingAlignment},
+	assignment::assign,
+	box_set::{select_box_set, BoxSet},
+	config::config,
+	disc_label::{detect_disc, DiscHint},
+	duplicates::{cluster_duplicates, pick_original, Duplicate, DuplicateAction},
+	edit_distance::{bounded_distance, tokenize},
 	extract_subtitles::extract_subtitles,
+	extras::{
+		classify, extra_confidence, extra_destination, typical_runtime, ExtraKind, TitleProfile,
+		PLAY_ALL_RATIO,
+	},
 	global_vars::TMDB_API_KEY,
-	interact::{interact, interact_async},
+	interact::{ensure_interactive, interact, is_unattended, set_unattended},
+	language::{set_language, Language},
+	naming::{Field, ShowInfo, Template, DEFAULT_TEMPLATE, LIBRARY_FOLDERS},
+	normalize::{normalize_subtitles, set_sdh_annotations, strip_sdh, strips_sdh, SdhAnnotations},
+	ocr_correction::{correct_ripped, corrected_path, OcrCorrection},
 	opensubtitles::get_subtitles,
+	plan::{Deletion, Plan, PlanEntry, Split, SplitPart},
+	play_all::{describe_timestamp, find_episodes, get_chapters, part_paths, EpisodeSpan},
+	runtime::{
+		describe_runtimes, episode_runtime, match_by_runtime, plausible_runtime, total_runtime,
+	},
+	similarity::{shortlist, Prefilter},
 	THEME,
 };
 
-pub async fn tag_items() -> anyhow::Result<()> {
-	let mut episodes = HashMap::<u32, Episode>::from_iter(
-		get_episodes_from_user()
-			.await?
+#[derive(Args)]
+pub struct TagArgs {
+	/// Engine used to find likely candidates before running a full comparison
+	#[arg(long, value_enum, default_value_t = Prefilter::MinHash)]
+	prefilter: Prefilter,
+
+	/// Number of candidate episodes per file that get a full comparison
+	#[arg(long, default_value_t = 3)]
+	candidates: usize,
+
+	/// Renames matches at or above this confidence (0 to 1) without asking
+	#[arg(long, value_name = "CONFIDENCE", value_parser = parse_confidence)]
+	auto_accept: Option<f64>,
+
+	/// TMDB id of the show, skipping the search
+	#[arg(long)]
+	tmdb_id: Option<u32>,
+
+	/// Takes the show named by the disc label when running unattended, instead of
+	/// requiring --tmdb-id. Only labels with a season or disc number that name exactly
+	/// one show are used.
+	#[arg(long, conflicts_with = "tmdb_id")]
+	trust_disc_label: bool,
+
+	/// TMDB id of the episode group (such as DVD order) that seasons and episodes are
+	/// numbered by, or "aired" for aired order. Asks when the show has any groups.
+	#[arg(long, value_name = "ID")]
+	episode_group: Option<String>,
+
+	/// Season included on this disc
+	#[arg(long)]
+	season: Option<u32>,
+
+	/// Episodes of the season included on this disc, such as "1-6,8"
+	#[arg(long, value_parser = parse_episode_ranges, requires = "season")]
+	episodes: Option<NumberRanges>,
+
+	/// Finds which episodes are on the disc instead of asking, by comparing it against
+	/// every season of the show, or only the given ones, such as "1-3". Specials are
+	/// only included when season 0 is given.
+	#[arg(
+		long,
+		value_name = "SEASONS",
+		num_args = 0..=1,
+		value_parser = parse_season_ranges,
+		conflicts_with = "season"
+	)]
+	auto_scope: Option<Option<NumberRanges>>,
+
+	/// Treats the disc as one of several holding the chosen seasons. It's compared
+	/// against every episode that an earlier disc hasn't already accounted for, without
+	/// asking which ones it has, and reference subtitles are kept for the next disc.
+	/// With --plan, the box sets are updated when the plan is applied.
+	#[arg(long, conflicts_with_all = ["episodes", "auto_scope"])]
+	box_set: bool,
+
+	/// Continues the box set of an earlier disc without asking for the show or season.
+	/// Asks which one when more than one is incomplete.
+	#[arg(
+		long,
+		conflicts_with_all = ["tmdb_id", "episode_group", "season", "episodes", "auto_scope"]
+	)]
+	resume: bool,
+
+	/// Language of the subtitles to compare, such as "de" or "ja". Defaults to the
+	/// `language` setting in the config file, or English.
+	#[arg(long, value_parser = Language::parse)]
+	language: Option<Language>,
+
+	/// What to do with annotations for the deaf and hard of hearing, such as
+	/// "[DOOR SLAMS]" or "JOHN:", in either set of subtitles. Defaults to the `sdh`
+	/// setting in the config file, or strip.
+	#[arg(long, value_enum, value_name = "ACTION")]
+	sdh: Option<SdhAnnotations>,
+
+	/// What to do about OCR errors, such as "rn" read instead of "m", in subtitles that
+	/// were extracted as images. They're corrected with the words of the reference
+	/// subtitles. Defaults to the `ocr_correction` setting in the config file, or apply.
+	#[arg(long, value_enum, value_name = "ACTION")]
+	ocr_correction: Option<OcrCorrection>,
+
+	/// Runs without prompting. Confirmations are answered with yes, except that
+	/// matches below --auto-accept are skipped, and any other missing input is an
+	/// error.
+	#[arg(short, long)]
+	yes: bool,
+
+	/// Writes the proposed changes to this file instead of renaming anything.
+	/// The plan can be reviewed or edited, then carried out with `apply`.
+	#[arg(long, value_name = "FILE")]
+	plan: Option<PathBuf>,
+
+	/// Where matched files are moved to, either as a template such as
+	/// "{show}/Season {season:02}/S{season:02}E{episode:02} - {title}" or one of the
+	/// presets: flat, plex, jellyfin or kodi. Defaults to the `template` setting in the
+	/// config file, or flat.
+	#[arg(long, value_parser = Template::parse)]
+	template: Option<Template>,
+
+	/// Moves matched files into this library folder, sorted into show and season
+	/// folders unless the template already creates its own
+	#[arg(long, value_name = "DIR")]
+	library: Option<PathBuf>,
+
+	/// Replaces episodes that are already there. Replaced files are kept in the trash
+	/// until the journal is pruned.
+	#[arg(long)]
+	replace: bool,
+
+	/// Where duplicates, play-all titles and unmatched titles as long as an episode are
+	/// moved. Defaults to the `quarantine` setting in the config file, or "Quarantine".
+	#[arg(long, value_name = "DIR")]
+	quarantine: Option<PathBuf>,
+
+	/// Leaves titles that don't match any episode where they are, instead of sorting
+	/// them into extras folders or the quarantine folder
+	#[arg(long)]
+	leave_unmatched: bool,
+
+	/// What to do with titles that are copies of another, such as alternate angles:
+	/// report them, move them to the quarantine folder, or delete them. Deleted titles
+	/// are kept in the trash until the journal is pruned. Defaults to quarantine, or
+	/// report with --leave-unmatched.
+	#[arg(long, value_enum, value_name = "ACTION")]
+	duplicates: Option<DuplicateAction>,
+
+	/// Splits titles that play several episodes back to back into a file per episode
+	/// with mkvmerge, for episodes that don't have a title of their own on the disc
+	#[arg(long)]
+	split_play_all: bool,
+}
+
+/// A list of episode or season numbers given on the command line
+#[derive(Debug, Clone)]
+pub struct NumberRanges(Vec<u32>);
+
+/// Parses range expressions such as "1-6,8"
+fn parse_ranges(value: &str, noun: &str) -> Result<NumberRanges, String> {
+	let mut numbers = Vec::<u32>::new();
+	for part in value
+		.split(',')
+		.map(str::trim)
+		.filter(|part| !part.is_empty())
+	{
+		let parse = |number: &str| -> Result<u32, String> {
+			return number
+				.trim()
+				.parse()
+				.map_err(|_| format!("{number:?} is not {noun} number"));
+		};
+		match part.split_once('-') {
+			Some((start, end)) => {
+				let (start, end) = (parse(start)?, parse(end)?);
+				if start > end {
+					return Err(format!("{part:?} is not a valid range"));
+				}
+				numbers.extend(start..=end);
+			}
+			None => numbers.push(parse(part)?),
+		}
+	}
+	if numbers.is_empty() {
+		return Err(String::from("no numbers were given"));
+	}
+	return Ok(NumberRanges(numbers));
+}
+
+fn parse_episode_ranges(value: &str) -> Result<NumberRanges, String> {
+	return parse_ranges(value, "an episode");
+}
+
+fn parse_season_ranges(value: &str) -> Result<NumberRanges, String> {
+	return parse_ranges(value, "a season");
+}
+
+pub fn parse_confidence(value: &str) -> Result<f64, String> {
+	let confidence: f64 = value
+		.parse()
+		.map_err(|_| format!("{value:?} is not a number"))?;
+	if !(0.0..=1.0).contains(&confidence) {
+		return Err(String::from("confidence must be between 0 and 1"));
+	}
+	return Ok(confidence);
+}
+
+pub async fn tag_items(args: TagArgs) -> anyhow::Result<()> {
+	set_unattended(args.yes);
+	set_language(args.language)?;
+	set_sdh_annotations(args.sdh.or(config().sdh).unwrap_or_default());
+	let mut template = match args.template {
+		Some(ref template) => template.clone(),
+		None => {
+			let template = config().template.as_deref().unwrap_or(DEFAULT_TEMPLATE);
+			Template::parse(template).map_err(|err| anyhow!("Invalid template in config: {err}"))?
+		}
+	};
+	if let Some(ref library) = args.library {
+		if !library.is_dir() {
+			return Err(anyhow!(
+				"Library folder {} doesn't exist",
+				library.display()
+			));
+		}
+		if !template.creates_folders() {
+			template = template.nested_in(&Template::parse(LIBRARY_FOLDERS).unwrap());
+		}
+	}
+
+	let (show, group, episodes) = get_episodes_from_user(&args).await?;
+	let mut box_sets = Vec::<BoxSet>::new();
+	if args.box_set || args.resume {
+		let mut seasons: Vec<u32> = episodes
+			.iter()
+			.map(|episode| episode.season_number)
+			.collect();
+		seasons.sort_unstable();
+		seasons.dedup();
+		for season in seasons {
+			let season_episodes: Vec<&Episode> = episodes
+				.iter()
+				.filter(|episode| episode.season_number == season)
+				.collect();
+			box_sets.push(BoxSet::open(&show, group.as_ref(), season, &season_episodes).await?);
+		}
+	}
+	let show = get_show_info(&show, &template).await?;
+	let is_tagged =
+		|episode: &Episode| box_sets.iter().any(|box_set| box_set.is_tagged(episode.id));
+	let tagged = episodes.iter().filter(|episode| is_tagged(episode)).count();
+	let episodes = HashMap::<u32, Episode>::from_iter(
+		episodes
 			.into_iter()
+			.filter(|episode| !is_tagged(episode))
 			.map(|episode| (episode.id, episode)),
 	);
+	if tagged > 0 {
+		println!("Leaving out {tagged} episode(s) that were tagged from earlier discs");
+	}
+	if !box_sets.is_empty() && episodes.is_empty() {
+		report_box_sets(&box_sets, &episodes);
+		return Ok(());
+	}
 
-	let manually_select_subs = interact(|| {
-		Confirm::with_theme(&*THEME)
-			.with_prompt("Would you like to select subtitles manually?")
-			.interact()
-	})
-	.await?;
-	let mut subtitle_files = HashMap::<u32, String>::default();
-	let mut missing_subtitles = Vec::<u32>::new();
+	let manually_select_subs = if is_unattended() {
+		false
+	} else {
+		interact(|| {
+			Confirm::with_theme(&*THEME)
+				.with_prompt("Would you like to select subtitles manually?")
+				.interact()
+		})
+		.await?
+	};
+	let mut subtitle_files = HashMap::<u32, Transcript>::default();
 	for episode in episodes.values() {
-		let subtitles = get_subtitles(episode, manually_select_subs).await;
+		let box_set = box_sets.iter().find(|box_set| box_set.contains(episode.id));
+		let cached = match box_set {
+			Some(box_set) => box_set.cached_reference(episode.id).await,
+			None => None,
+		};
+		let downloaded = cached.is_none();
+		let subtitles = match cached {
+			Some(subtitles) => Ok(subtitles),
+			None => get_subtitles(episode.id, manually_select_subs).await,
+		};
 		match subtitles {
 			Ok(subtitles) => {
-				subtitle_files.insert(episode.id, strip_subtitles(&subtitles));
+				if let Some(box_set) = box_set.filter(|_| downloaded) {
+					box_set.cache_reference(episode.id, &subtitles).await?;
+				}
+				subtitle_files.insert(episode.id, Transcript::new(&subtitles));
 			}
 			Err(_) => {
-				missing_subtitles.push(episode.id);
 				println!(
 					"Skipping S{:02}E{:02}. No subtitles found.",
 					episode.season_number, episode.episode_number
@@ -54,162 +324,1123 @@ pub async fn tag_items() -> anyhow::Result<()> {
 			}
 		}
 	}
-	for episode_id in missing_subtitles {
-		episodes.remove(&episode_id);
+
+	for box_set in &mut box_sets {
+		box_set.save().await?;
+	}
+	let annotated = subtitle_files
+		.values()
+		.filter(|transcript| transcript.annotations > 0)
+		.count();
+	if annotated > 0 {
+		println!(
+			"Removed SDH annotations from {annotated} of {} reference subtitles",
+			subtitle_files.len()
+		);
 	}
 
-	// Get list of subtitle files without extensions and their contents
-	let mut files = get_subtitle_files(".").await?;
-	if files.len() == 0 {
-		let new_files = interact_async(async {
-			let should_extract = task::spawn_blocking(|| {
-				Confirm::with_theme(&*THEME)
-					.with_prompt("Subtitles not found. Would you like to extract them?")
-					.interact()
-			})
-			.await??;
-			if should_extract {
-				extract_subtitles(false, None).await?;
-				eprintln!("Got subtitles");
-				let new_files = get_subtitle_files(".").await?;
-				eprintln!("Listing new files");
-				return Ok(Some(new_files));
-			} else {
-				return anyhow::Result::<Option<Vec<(PathBuf, String)>>>::Ok(None);
+	let Some(mut files) = get_ripped_subtitles().await? else {
+		return Ok(());
+	};
+	let references: Vec<&Transcript> = subtitle_files.values().collect();
+	let ocr_correction = args.ocr_correction.or(config().ocr_correction);
+	correct_ripped(&mut files, &references, ocr_correction.unwrap_or_default()).await?;
+
+	// Without any subtitles to compare, the best we can do is go by runtime and the
+	// order of the titles on the disc
+	if subtitle_files.is_empty() || files.iter().all(|(_, contents)| contents.length == 0) {
+		println!("There are no subtitles to compare, so titles will be matched by runtime");
+		return tag_by_runtime(&args, &files, &episodes, &template, &show, &mut box_sets).await;
+	}
+
+	// Alternate angles and seamless branches of the same episode would all compete for
+	// it, so only the most complete title of each is matched
+	let durations: Vec<Option<Duration>> = files
+		.iter()
+		.map(|(path, _)| get_duration(&path.with_extension("mkv")))
+		.collect();
+	let mut sizes = Vec::<u64>::new();
+	for (path, _) in &files {
+		let metadata = fs::metadata(path.with_extension("mkv")).await;
+		sizes.push(metadata.map_or(0, |metadata| metadata.len()));
+	}
+	let transcripts: Vec<&Transcript> = files.iter().map(|(_, contents)| contents).collect();
+	let mut duplicates = HashMap::<usize, Duplicate>::new();
+	for cluster in cluster_duplicates(&transcripts) {
+		let original = pick_original(&cluster, &durations, &sizes);
+		for (file_idx, similarity) in cluster {
+			if file_idx != original {
+				duplicates.insert(
+					file_idx,
+					Duplicate {
+						original,
+						similarity,
+					},
+				);
 			}
+		}
+	}
+	let duplicate_action = args.duplicates.unwrap_or(if args.leave_unmatched {
+		DuplicateAction::Report
+	} else {
+		DuplicateAction::Quarantine
+	});
+
+	if args.auto_scope.is_some() {
+		let Some(present) =
+			confirm_scope(&files, &subtitle_files, &episodes, &durations, &args).await?
+		else {
+			println!("Use --season and --episodes to choose the episodes yourself.");
+			return Ok(());
+		};
+		subtitle_files.retain(|episode_id, _| present.contains(episode_id));
+	}
+
+	// Keep the episodes in a stable order so they can be referred to by index
+	let mut episode_list: Vec<&Episode> = episodes
+		.values()
+		.filter(|episode| subtitle_files.contains_key(&episode.id))
+		.collect();
+	episode_list.sort_unstable_by_key(|episode| (episode.season_number, episode.episode_number));
+
+	// Titles with several episodes' worth of dialogue might be play-all titles. Those
+	// are located episode by episode rather than compared as a whole.
+	let mut lengths: Vec<usize> = subtitle_files
+		.values()
+		.map(|transcript| transcript.length)
+		.collect();
+	lengths.sort_unstable();
+	let typical_length = lengths.get(lengths.len() / 2).copied().unwrap_or(0);
+	let references: Vec<(u32, &Transcript)> = episode_list
+		.iter()
+		.map(|episode| (episode.id, subtitle_files.get(&episode.id).unwrap()))
+		.collect();
+	let mut play_alls = HashMap::<usize, Vec<EpisodeSpan>>::new();
+	for (file_idx, (path, contents)) in files.iter().enumerate() {
+		if typical_length == 0
+			|| (contents.length as f64) < typical_length as f64 * PLAY_ALL_RATIO
+			|| duplicates.contains_key(&file_idx)
+		{
+			continue;
+		}
+		let chapters = get_chapters(&path.with_extension("mkv"));
+		if let Some(spans) = find_episodes(contents, &chapters, &references) {
+			play_alls.insert(file_idx, spans);
+		}
+	}
+
+	// Two-part episodes are often authored as a single title, so long titles are also
+	// compared against runs of consecutive episodes. Those are extra columns after the
+	// single episodes.
+	let combos = consecutive_runs(&episode_list);
+	let combo_transcripts: Vec<Transcript> = combos
+		.iter()
+		.map(|combo| {
+			let parts: Vec<&Transcript> = combo
+				.iter()
+				.map(|episode_idx| references[*episode_idx].1)
+				.collect();
+			Transcript::concat(&parts)
 		})
-		.await?;
-		match new_files {
-			Some(new_files) => files = new_files,
-			None => {
-				println!("Cannot continue without subtitles.");
-				return Ok(());
+		.collect();
+	let combo_runtimes: Vec<Option<Duration>> = combos
+		.iter()
+		.map(|combo| {
+			let contained: Vec<&Episode> = combo
+				.iter()
+				.map(|episode_idx| episode_list[*episode_idx])
+				.collect();
+			total_runtime(&contained)
+		})
+		.collect();
+	let column_count = episode_list.len() + combos.len();
+	let column_episodes = |column: usize| -> Vec<usize> {
+		match column.checked_sub(episode_list.len()) {
+			Some(combo_idx) => combos[combo_idx].clone(),
+			None => vec![column],
+		}
+	};
+	let column_transcript = |column: usize| -> &Transcript {
+		match column.checked_sub(episode_list.len()) {
+			Some(combo_idx) => &combo_transcripts[combo_idx],
+			None => references[column].1,
+		}
+	};
+
+	// Narrow down which pairs are worth comparing in full
+	let runtimes: Vec<Option<Duration>> = episode_list
+		.iter()
+		.map(|episode| episode_runtime(episode))
+		.collect();
+	let mut shortlists = match args.prefilter.engine() {
+		Some(engine) => {
+			let references: Vec<&str> = episode_list
+				.iter()
+				.map(|episode| subtitle_files.get(&episode.id).unwrap().text.as_str())
+				.collect();
+			let candidates: Vec<&str> = files
+				.iter()
+				.map(|(_, contents)| contents.text.as_str())
+				.collect();
+			let mut similarities = engine.similarities(&references, &candidates);
+			for (file_idx, row) in similarities.iter_mut().enumerate() {
+				for (episode_idx, similarity) in row.iter_mut().enumerate() {
+					if !plausible_runtime(durations[file_idx], runtimes[episode_idx]) {
+						*similarity = f64::NEG_INFINITY;
+					}
+				}
 			}
+			shortlist(&similarities, args.candidates.max(1))
 		}
+		None => files
+			.iter()
+			.map(|_| (0..episode_list.len()).collect())
+			.collect(),
+	};
+
+	// A title can't be an episode with a wildly different runtime, however alike the
+	// subtitles might look
+	for (file_idx, shortlist) in shortlists.iter_mut().enumerate() {
+		shortlist
+			.retain(|episode_idx| plausible_runtime(durations[file_idx], runtimes[*episode_idx]));
+	}
+	for file_idx in play_alls.keys().chain(duplicates.keys()) {
+		shortlists[*file_idx].clear();
 	}
 
-	// Order potential matches by likeness
-	// eprintln!("Running levenshtein distances...");
-	let mut matches = Vec::<(u32, usize, &PathBuf)>::new();
+	// Compare each file against the episodes on its shortlist, most promising first so
+	// the rest can be abandoned as soon as they fall behind.
+	let mut matches: Vec<Vec<Option<Match>>> = files
+		.iter()
+		.map(|_| (0..column_count).map(|_| None).collect())
+		.collect();
 	rayon::scope(|s| {
-		let (lev_sender, mut lev_receiver) = mpsc::unbounded_channel::<(u32, usize, &PathBuf)>();
-		for episode in episodes.values() {
-			for (file, contents) in &files {
-				// let episode_id = episode.id;
+		let (lev_sender, mut lev_receiver) = mpsc::unbounded_channel::<(usize, usize, Match)>();
+		for (file_idx, (_, contents)) in files.iter().enumerate() {
+			let lev_sender = lev_sender.clone();
+			let subtitle_files = &subtitle_files;
+			let shortlist = &shortlists[file_idx];
+			let episode_list = &episode_list;
+			s.spawn(move |_| {
+				let mut best_rank: Option<usize> = None;
+				for &episode_idx in shortlist {
+					let episode = episode_list[episode_idx];
+					let reference = subtitle_files.get(&episode.id).unwrap();
+					let entry = Match::compare(reference, contents, best_rank);
+					if entry.exact {
+						best_rank =
+							Some(best_rank.map_or(entry.rank(), |rank| rank.min(entry.rank())));
+					}
+					lev_sender.send((file_idx, episode_idx, entry)).unwrap();
+				}
+			});
+		}
+		for (file_idx, (_, contents)) in files.iter().enumerate() {
+			if (contents.length as f64) < typical_length as f64 * MULTI_EPISODE_RATIO
+				|| duplicates.contains_key(&file_idx)
+			{
+				continue;
+			}
+			for (combo_idx, reference) in combo_transcripts.iter().enumerate() {
+				if !plausible_runtime(durations[file_idx], combo_runtimes[combo_idx]) {
+					continue;
+				}
 				let lev_sender = lev_sender.clone();
-				let subtitle_files = &subtitle_files;
+				let column = episode_list.len() + combo_idx;
 				s.spawn(move |_| {
-					let distance =
-						strsim::levenshtein(subtitle_files.get(&episode.id).unwrap(), contents);
-					lev_sender.send((episode.id, distance, file)).unwrap();
+					let entry = Match::compare(reference, contents, None);
+					lev_sender.send((file_idx, column, entry)).unwrap();
 				});
 			}
 		}
 		drop(lev_sender);
-		while let Some(entry) = lev_receiver.blocking_recv() {
-			// eprintln!("Finished distance for episode {} against {}: {}", entry.0, entry.2.file_name().unwrap().to_str().unwrap(), entry.1);
-			matches.push(entry);
+		while let Some((file_idx, column, entry)) = lev_receiver.blocking_recv() {
+			matches[file_idx][column] = Some(entry);
 		}
 	});
-	matches.sort_unstable();
-	// eprintln!("Finished levelshtein distances");
-
-	// Match against the known files first to eliminate extras and such.
-	let mut matches_by_episode = HashMap::<u32, Vec<(&PathBuf, usize)>>::new();
-	for (episode_id, likeness, path) in matches {
-		match matches_by_episode.get_mut(&episode_id) {
-			Some(results) => {
-				results.push((path, likeness));
-			}
-			None => {
-				matches_by_episode.insert(episode_id, vec![(path, likeness)]);
+
+	// Solve all of the files at once so no two files can claim the same episode
+	let mut excluded = HashSet::<(usize, usize)>::new();
+	let assignments = loop {
+		let costs: Vec<Vec<f64>> = matches
+			.iter()
+			.enumerate()
+			.map(|(file_idx, row)| {
+				row.iter()
+					.enumerate()
+					.map(|(column, entry)| match entry {
+						Some(entry) if !excluded.contains(&(file_idx, column)) => entry.cost(),
+						_ => 1.0,
+					})
+					.collect()
+			})
+			.collect();
+		let assignments = assign(&costs, UNASSIGNED_COST);
+
+		// Abandoned comparisons only know a lower bound for their distance. If one of
+		// them got picked anyway, finish it and try again with the real value.
+		let mut refined = false;
+		for (file_idx, assignment) in assignments.iter().enumerate() {
+			let Some(column) = *assignment else {
+				continue;
+			};
+			let entry = matches[file_idx][column].as_mut().unwrap();
+			if !entry.exact {
+				entry.distance = bounded_distance(
+					&column_transcript(column).tokens,
+					&files[file_idx].1.tokens,
+					usize::MAX,
+				)
+				.unwrap();
+				entry.exact = true;
+				refined = true;
 			}
 		}
-	}
-
-	let mut matches_by_file = HashMap::<&PathBuf, Vec<(usize, &Episode)>>::new();
-	for (episode_id, matches) in matches_by_episode {
-		if matches.len() == 0 {
+		if refined {
 			continue;
 		}
-		for (file_path, distance) in matches {
-			match matches_by_file.get_mut(file_path) {
-				Some(file_matches) => {
-					file_matches.push((distance, episodes.get(&episode_id).unwrap()));
+
+		// The assignment only stops two files from claiming the same column, so a file
+		// with several episodes could still share one with another file. Files with
+		// single episodes are kept over ones that combine them.
+		let mut conflicted = false;
+		for (file_idx, assignment) in assignments.iter().enumerate() {
+			let Some(column) = assignment.filter(|column| *column >= episode_list.len()) else {
+				continue;
+			};
+			let consumed = column_episodes(column);
+			for (other_idx, other) in assignments.iter().enumerate() {
+				let Some(other) = *other else {
+					continue;
+				};
+				if other_idx == file_idx
+					|| !column_episodes(other)
+						.iter()
+						.any(|idx| consumed.contains(idx))
+				{
+					continue;
 				}
-				None => {
-					matches_by_file.insert(
-						file_path,
-						vec![(distance, episodes.get(&episode_id).unwrap())],
-					);
+				let other_cost = matches[other_idx][other].as_ref().unwrap().cost();
+				let cost = matches[file_idx][column].as_ref().unwrap().cost();
+				if other < episode_list.len() || other_cost <= cost {
+					excluded.insert((file_idx, column));
+				} else {
+					excluded.insert((other_idx, other));
 				}
+				conflicted = true;
 			}
 		}
-	}
+		if !conflicted {
+			break assignments;
+		}
+	};
 
-	for (file_path, matches) in matches_by_file.iter_mut() {
-		matches.sort_unstable_by_key(|sort| sort.0);
+	// Whatever doesn't match an episode is sorted by how it compares to the ones that
+	// did. Extras are kept with the show, next to the season folders.
+	let episode_runtime = typical_runtime(
+		assignments
+			.iter()
+			.zip(&durations)
+			.filter(|(assignment, _)| assignment.is_some_and(|column| column < episode_list.len()))
+			.filter_map(|(_, duration)| *duration)
+			.collect(),
+	)
+	.or_else(|| {
+		typical_runtime(
+			episode_list
+				.iter()
+				.filter_map(|episode| episode_runtime(episode))
+				.collect(),
+		)
+	});
+	let show_folder = template.show_folder(&show).unwrap_or_default();
+	let extras_root = match args.library {
+		Some(ref library) => library.join(show_folder),
+		None => show_folder,
+	};
 
-		let mkv_file = file_path.with_extension("mkv");
-		let srt_file = file_path.with_extension("srt");
+	// Episodes in a play-all title are only split out if no other title has them
+	let mut claimed: HashSet<u32> = assignments
+		.iter()
+		.flatten()
+		.flat_map(|column| column_episodes(*column))
+		.map(|episode_idx| episode_list[episode_idx].id)
+		.collect();
 
-		let mut rename_to = match matches.len() {
-			0 => {
-				println!("{:?} => ??? (No match found)", *file_path);
-				None
+	// Show the whole plan before touching anything on disk
+	let mut plan = Plan::default();
+	println!("Proposed changes:");
+	for (file_idx, ((file_path, transcript), assignment)) in
+		files.iter().zip(&assignments).enumerate()
+	{
+		let mkv_file = file_path.with_extension("mkv");
+		let Some(column) = *assignment else {
+			if let Some(duplicate) = duplicates.get(&file_idx) {
+				let original = files[duplicate.original].0.with_extension("mkv");
+				let sidecars = sidecars(file_path);
+				match duplicate_action {
+					DuplicateAction::Report => {
+						println!("{:?} => ??? (Copy of {:?})", &mkv_file, original);
+						plan.unmatched.push(mkv_file);
+					}
+					DuplicateAction::Delete => {
+						println!(
+							"{:?} => trash (copy of {:?})\n    similarity:       {:.2}",
+							&mkv_file, original, duplicate.similarity
+						);
+						plan.deletions.push(Deletion {
+							path: mkv_file,
+							sidecars,
+						});
+					}
+					DuplicateAction::Quarantine => {
+						let target = extra_destination(
+							ExtraKind::Duplicate,
+							&extras_root,
+							args.quarantine.as_deref(),
+							&mkv_file,
+						);
+						let confidence = extra_confidence(duplicate.similarity, true);
+						println!(
+							"{:?} => {:?} (copy of {:?})\n    similarity:       {:.2}\n    confidence:       {:.2}",
+							&mkv_file, &target, original, duplicate.similarity, confidence
+						);
+						plan.entries.push(PlanEntry {
+							source: mkv_file,
+							target,
+							episode_ids: Vec::new(),
+							sidecars,
+							similarity: duplicate.similarity,
+							confidence,
+							replace: args.replace,
+							extra: Some(ExtraKind::Duplicate),
+						});
+					}
+				}
+				continue;
 			}
-			1 => {
-				let filename = format_filename(matches[0].1);
+			let spans = play_alls.get(&file_idx);
+			if let Some(spans) = spans {
 				println!(
-					"{:?} => {:?}\n    distance:         {}\n    closest_negative: n/a",
-					&mkv_file, &filename, matches[0].0
+					"{:?} plays {} episodes back to back:",
+					&mkv_file,
+					spans.len()
 				);
-				Some(filename)
+				for span in spans {
+					let episode = episodes.get(&span.episode_id).unwrap();
+					println!(
+						"    S{:02}E{:02} starts at {} ({}, {:.0}% of the dialogue matches)",
+						episode.season_number,
+						episode.episode_number,
+						describe_timestamp(span.start),
+						if span.chapter {
+							"chapter mark"
+						} else {
+							"subtitle timing"
+						},
+						span.containment * 100.0,
+					);
+				}
+				if args.split_play_all {
+					if let Some((split, entries)) = split_play_all(
+						&mkv_file,
+						spans,
+						&mut claimed,
+						&episodes,
+						&template,
+						&show,
+						&args,
+					)? {
+						plan.splits.push(split);
+						plan.entries.extend(entries);
+					}
+				}
 			}
-			_ => {
-				let filename = format_filename(matches[0].1);
-				println!(
-					"{:?} => {:?}\n    distance:         {}\n    closest negative: {}",
-					&mkv_file, &filename, matches[0].0, matches[1].0
-				);
-				Some(filename)
+			if args.leave_unmatched {
+				println!("{:?} => ??? (No match found)", &mkv_file);
+				plan.unmatched.push(mkv_file);
+				continue;
 			}
+			let closest = matches[file_idx]
+				.iter()
+				.flatten()
+				.max_by(|a, b| a.similarity().total_cmp(&b.similarity()));
+			// Matching an episode well, but losing it to another file, makes a duplicate
+			let duplicate = closest.is_some_and(|closest| {
+				closest.exact && closest.similarity() >= 1.0 - UNASSIGNED_COST
+			});
+			let profile = TitleProfile {
+				duration: durations[file_idx],
+				words: transcript.length,
+				duplicate,
+			};
+			let kind = match spans {
+				Some(_) => ExtraKind::PlayAll,
+				None => classify(&profile, episode_runtime),
+			};
+			let target =
+				extra_destination(kind, &extras_root, args.quarantine.as_deref(), &mkv_file);
+			let similarity = closest.map_or(0.0, Match::similarity);
+			let confidence = extra_confidence(similarity, duplicate);
+			println!(
+				"{:?} => {:?} ({})\n    closest match:    {}\n    confidence:       {:.2}\n    runtime:          {}",
+				&mkv_file,
+				&target,
+				kind.describe(),
+				closest
+					.map(Match::describe_similarity)
+					.unwrap_or_else(|| String::from("n/a")),
+				confidence,
+				profile
+					.duration
+					.map_or_else(|| String::from("unknown"), describe_runtime),
+			);
+			plan.entries.push(PlanEntry {
+				source: mkv_file,
+				target,
+				episode_ids: Vec::new(),
+				sidecars: sidecars(file_path),
+				similarity,
+				confidence,
+				replace: args.replace,
+				extra: Some(kind),
+			});
+			continue;
 		};
+		let best = matches[file_idx][column].as_ref().unwrap();
+
+		// The closest competitor is either another episode for this file, or another
+		// file that would have liked this episode.
+		let other_episodes = matches[file_idx]
+			.iter()
+			.enumerate()
+			.filter(|(idx, _)| *idx != column)
+			.filter_map(|(_, entry)| entry.as_ref());
+		let other_files = matches
+			.iter()
+			.enumerate()
+			.filter(|(idx, _)| *idx != file_idx)
+			.filter_map(|(_, row)| row[column].as_ref());
+		let runner_up = other_episodes
+			.chain(other_files)
+			.max_by(|a, b| a.similarity().total_cmp(&b.similarity()));
+		let confidence = best.confidence(runner_up);
 
-		let rename = interact(|| {
+		let contained: Vec<&Episode> = column_episodes(column)
+			.into_iter()
+			.map(|episode_idx| episode_list[episode_idx])
+			.collect();
+		let mut filename = template.render(&show, &contained, "mkv");
+		if let Some(ref library) = args.library {
+			filename = library.join(filename);
+		}
+		println!(
+			"{:?} => {:?}\n    similarity:       {}\n    closest negative: {}\n    confidence:       {:.2}\n    timing:           {}\n    runtime:          {}",
+			&mkv_file,
+			&filename,
+			best.describe_similarity(),
+			runner_up
+				.map(Match::describe_similarity)
+				.unwrap_or_else(|| String::from("n/a")),
+			confidence,
+			describe_timing(&best.timing),
+			describe_runtimes(durations[file_idx], total_runtime(&contained)),
+		);
+		if let Some(sdh) = describe_sdh(column_transcript(column), transcript) {
+			println!("    sdh:              {sdh}");
+		}
+		if fs::try_exists(&filename).await? {
+			if args.replace {
+				println!("    replaces the existing file");
+			} else {
+				println!("    already exists, and will be skipped");
+			}
+		}
+		plan.entries.push(PlanEntry {
+			source: mkv_file,
+			target: filename,
+			episode_ids: contained.iter().map(|episode| episode.id).collect(),
+			sidecars: sidecars(file_path),
+			similarity: best.similarity(),
+			confidence,
+			replace: args.replace,
+			extra: None,
+		});
+	}
+
+	return carry_out(plan, &args, &mut box_sets, &episodes).await;
+}
+
+/// Writes the plan out if that was asked for, or otherwise renames the files and
+/// updates any box sets with the episodes that were tagged
+async fn carry_out(
+	mut plan: Plan,
+	args: &TagArgs,
+	box_sets: &mut [BoxSet],
+	episodes: &HashMap<u32, Episode>,
+) -> anyhow::Result<()> {
+	if let Some(ref plan_path) = args.plan {
+		plan.box_sets = box_sets.iter().map(BoxSet::key).collect();
+		plan.write(plan_path).await?;
+		println!("Wrote plan to {:?}", plan_path);
+		return Ok(());
+	}
+
+	// Whatever was renamed before a failure still belongs in the box sets
+	let mut applied = Vec::<&PlanEntry>::new();
+	let result = plan.confirm_and_apply(args.auto_accept, &mut applied).await;
+	if box_sets.is_empty() {
+		return result;
+	}
+	for box_set in box_sets.iter_mut() {
+		box_set.record(&applied)?;
+		box_set.save().await?;
+	}
+	report_box_sets(box_sets, episodes);
+	return result;
+}
+
+/// Says which box sets are complete, and which episodes the rest are missing
+fn report_box_sets(box_sets: &[BoxSet], episodes: &HashMap<u32, Episode>) {
+	for box_set in box_sets {
+		println!(
+			"{}",
+			box_set.describe_progress(|episode_id| {
+				episodes
+					.get(&episode_id)
+					.map(|episode| episode.episode_number)
+			})
+		);
+	}
+}
+
+/// Narrows a whole show down to the episodes that seem to be on the disc, going only
+/// by the prefilter, and has the user confirm them. Returns `None` if they didn't.
+async fn confirm_scope(
+	files: &[(PathBuf, Transcript)],
+	subtitle_files: &HashMap<u32, Transcript>,
+	episodes: &HashMap<u32, Episode>,
+	durations: &[Option<Duration>],
+	args: &TagArgs,
+) -> anyhow::Result<Option<HashSet<u32>>> {
+	let mut episode_list: Vec<&Episode> = episodes
+		.values()
+		.filter(|episode| subtitle_files.contains_key(&episode.id))
+		.collect();
+	episode_list.sort_unstable_by_key(|episode| (episode.season_number, episode.episode_number));
+	let references: Vec<&str> = episode_list
+		.iter()
+		.map(|episode| subtitle_files.get(&episode.id).unwrap().text.as_str())
+		.collect();
+	let candidates: Vec<&str> = files
+		.iter()
+		.map(|(_, contents)| contents.text.as_str())
+		.collect();
+	// Comparing everything in full is what this is meant to avoid, so some prefilter
+	// is always used
+	let engine = args
+		.prefilter
+		.engine()
+		.unwrap_or_else(|| Prefilter::MinHash.engine().unwrap());
+	let mut similarities = engine.similarities(&references, &candidates);
+	for (file_idx, row) in similarities.iter_mut().enumerate() {
+		for (episode_idx, similarity) in row.iter_mut().enumerate() {
+			if !plausible_runtime(
+				durations[file_idx],
+				episode_runtime(episode_list[episode_idx]),
+			) {
+				*similarity = 0.0;
+			}
+		}
+	}
+	let mut present: Vec<&Episode> = Vec::new();
+	for (file_idx, shortlist) in shortlist(&similarities, args.candidates.max(1))
+		.iter()
+		.enumerate()
+	{
+		for episode_idx in shortlist {
+			let episode = episode_list[*episode_idx];
+			if similarities[file_idx][*episode_idx] > 0.0
+				&& !present.iter().any(|other| other.id == episode.id)
+			{
+				present.push(episode);
+			}
+		}
+	}
+	if present.is_empty() {
+		return Err(anyhow!("None of the episodes seem to be on this disc"));
+	}
+	present.sort_unstable_by_key(|episode| (episode.season_number, episode.episode_number));
+
+	println!(
+		"The disc seems to contain {} of {} episodes: {}",
+		present.len(),
+		episode_list.len(),
+		describe_episodes(&present)
+	);
+	let accepted = is_unattended()
+		|| interact(|| {
 			Confirm::with_theme(&*THEME)
-				.with_prompt("Rename file?")
+				.with_prompt("Compare the disc against these episodes?")
+				.default(true)
 				.interact()
 		})
 		.await?;
-		if !rename {
-			rename_to = None;
+	if !accepted {
+		return Ok(None);
+	}
+	return Ok(Some(present.iter().map(|episode| episode.id).collect()));
+}
+
+/// Lists episodes compactly, such as "S01E01-E06, S01E08, S02E01"
+fn describe_episodes(episodes: &[&Episode]) -> String {
+	let mut runs = Vec::<(&Episode, &Episode)>::new();
+	for episode in episodes {
+		match runs.last_mut() {
+			Some((_, last))
+				if last.season_number == episode.season_number
+					&& last.episode_number + 1 == episode.episode_number =>
+			{
+				*last = episode;
+			}
+			_ => runs.push((episode, episode)),
 		}
-		if let Some(rename_to) = rename_to {
-			fs::rename(mkv_file, rename_to)
-				.await
-				.context("Couldn't rename mkv file")?;
-			fs::remove_file(srt_file)
-				.await
-				.context("Failed to remove srt file")?;
+	}
+	return runs
+		.iter()
+		.map(|(first, last)| {
+			let mut run = format!("S{:02}E{:02}", first.season_number, first.episode_number);
+			if last.episode_number != first.episode_number {
+				run.push_str(&format!("-E{:02}", last.episode_number));
+			}
+			run
+		})
+		.collect::<Vec<String>>()
+		.join(", ");
+}
+
+/// Matches the ripped titles on a disc without subtitles, by their runtimes and the
+/// order they're in
+async fn tag_by_runtime(
+	args: &TagArgs,
+	files: &[(PathBuf, Transcript)],
+	episodes: &HashMap<u32, Episode>,
+	template: &Template,
+	show: &ShowInfo,
+	box_sets: &mut [BoxSet],
+) -> anyhow::Result<()> {
+	let durations: Vec<Option<Duration>> = files
+		.iter()
+		.map(|(file_path, _)| get_duration(&file_path.with_extension("mkv")))
+		.collect();
+
+	let mut episode_list: Vec<&Episode> = episodes.values().collect();
+	episode_list.sort_unstable_by_key(|episode| (episode.season_number, episode.episode_number));
+	let runtimes: Vec<Option<Duration>> = episode_list
+		.iter()
+		.map(|episode| episode_runtime(episode))
+		.collect();
+	let matches = match_by_runtime(&durations, &runtimes);
+
+	let mut plan = Plan::default();
+	println!("Proposed changes:");
+	for (((file_path, _), duration), best) in files.iter().zip(durations).zip(matches) {
+		let file = file_path.with_extension("mkv");
+		let Some(best) = best else {
+			println!("{:?} => ??? (No match found)", &file);
+			plan.unmatched.push(file);
+			continue;
+		};
+		let episode = episode_list[best.episode_idx];
+		let mut filename = template.render(show, &[episode], "mkv");
+		if let Some(ref library) = args.library {
+			filename = library.join(filename);
+		}
+		println!(
+			"{:?} => {:?}\n    matched by:       runtime and disc order\n    confidence:       {:.2}\n    runtime:          {}",
+			&file,
+			&filename,
+			best.confidence,
+			describe_runtimes(duration, runtimes[best.episode_idx]),
+		);
+		plan.entries.push(PlanEntry {
+			source: file,
+			target: filename,
+			episode_ids: vec![episode.id],
+			sidecars: sidecars(file_path),
+			similarity: 1.0 - best.cost,
+			confidence: best.confidence,
+			replace: args.replace,
+			extra: None,
+		});
+	}
+
+	return carry_out(plan, args, box_sets, episodes).await;
+}
+
+/// Plans for the episodes of a play-all title that no other title has to be split into
+/// files of their own, and renamed like any other episode. Returns `None` if there's
+/// nothing to split.
+fn split_play_all(
+	file: &Path,
+	spans: &[EpisodeSpan],
+	claimed: &mut HashSet<u32>,
+	episodes: &HashMap<u32, Episode>,
+	template: &Template,
+	show: &ShowInfo,
+	args: &TagArgs,
+) -> anyhow::Result<Option<(Split, Vec<PlanEntry>)>> {
+	let mut wanted = Vec::<(&EpisodeSpan, Option<Duration>)>::new();
+	for (idx, span) in spans.iter().enumerate() {
+		if claimed.insert(span.episode_id) {
+			wanted.push((span, spans.get(idx + 1).map(|next| next.start)));
+		}
+	}
+	if wanted.is_empty() {
+		println!("    every episode has a title of its own, so it won't be split");
+		return Ok(None);
+	}
+	let mut split = Split {
+		source: file.to_owned(),
+		parts: Vec::new(),
+	};
+	let mut entries = Vec::<PlanEntry>::new();
+	let paths = part_paths(file, wanted.len())?;
+	for ((span, end), part) in wanted.into_iter().zip(paths) {
+		split.parts.push(SplitPart {
+			path: part.clone(),
+			start: span.start,
+			end,
+		});
+		let mut filename = template.render(show, &[episodes.get(&span.episode_id).unwrap()], "mkv");
+		if let Some(ref library) = args.library {
+			filename = library.join(filename);
 		}
+		println!(
+			"{:?} => {:?}\n    split from:       {:?} at {}\n    confidence:       {:.2}",
+			&part,
+			&filename,
+			file,
+			describe_timestamp(span.start),
+			span.confidence(),
+		);
+		entries.push(PlanEntry {
+			source: part,
+			target: filename,
+			episode_ids: vec![span.episode_id],
+			sidecars: Vec::new(),
+			similarity: span.containment,
+			confidence: span.confidence(),
+			replace: args.replace,
+			extra: None,
+		});
 	}
+	return Ok(Some((split, entries)));
+}
+
+/// Titles with at least this many times the dialogue of a typical episode are also
+/// compared against runs of consecutive episodes
+const MULTI_EPISODE_RATIO: f64 = 1.5;
+
+/// Longest run of episodes that's considered to be authored as a single title
+const MAX_EPISODES_PER_FILE: usize = 3;
 
-	return Ok(());
+/// Lists the runs of consecutive episodes from the same season that a single title
+/// could contain, as indexes into `episode_list`
+fn consecutive_runs(episode_list: &[&Episode]) -> Vec<Vec<usize>> {
+	let mut runs = Vec::<Vec<usize>>::new();
+	for start in 0..episode_list.len() {
+		let mut run = vec![start];
+		for next in start + 1..episode_list.len().min(start + MAX_EPISODES_PER_FILE) {
+			let (previous, episode) = (episode_list[next - 1], episode_list[next]);
+			if episode.season_number != previous.season_number
+				|| episode.episode_number != previous.episode_number + 1
+			{
+				break;
+			}
+			run.push(next);
+			runs.push(run.clone());
+		}
+	}
+	return runs;
 }
 
-fn format_filename(episode: &Episode) -> PathBuf {
-	return PathBuf::from(format!(
-		"S{:02}E{:02} - {}.mkv",
-		episode.season_number, episode.episode_number, episode.name
+/// How much a perfect timing alignment is allowed to shrink the text distance.
+/// Timing can't tell episodes of a show apart on its own, but it breaks ties when the
+/// text is noisy, such as with OCR'd subtitles.
+const TIMING_WEIGHT: f64 = 0.5;
+
+/// Cost at which a file is better left alone than given an episode. Costs run from
+/// 0 for identical transcripts to 1 for ones that have nothing in common, with
+/// unrelated episodes of the same show usually landing somewhere above this.
+pub const UNASSIGNED_COST: f64 = 0.6;
+
+/// Comparison results between one reference episode and one ripped file
+pub struct Match {
+	/// Edit distance in words
+	distance: usize,
+	/// Whether the comparison ran to completion. If it was abandoned early, `distance`
+	/// is only a lower bound.
+	pub exact: bool,
+	/// Length of the longer of the two transcripts, which bounds the distance
+	length: usize,
+	pub timing: TimingAlignment,
+}
+impl Match {
+	/// Compares a ripped transcript against a reference. Past the point where leaving
+	/// the file unassigned is cheaper, or where it can no longer beat `best_rank`, the
+	/// exact distance is moot and the comparison is abandoned.
+	pub fn compare(reference: &Transcript, ripped: &Transcript, best_rank: Option<usize>) -> Self {
+		let timing = align_cues(&reference.cues, &ripped.cues);
+		let length = reference.length.max(ripped.length);
+		let discount = 1.0 - TIMING_WEIGHT * timing.score;
+		let mut bound = (UNASSIGNED_COST * length as f64 / discount) as usize;
+		if let Some(best_rank) = best_rank {
+			bound = bound.min((best_rank as f64 / discount) as usize);
+		}
+		let distance = bounded_distance(&reference.tokens, &ripped.tokens, bound);
+		return Self {
+			distance: distance.unwrap_or(bound + 1),
+			exact: distance.is_some(),
+			length,
+			timing,
+		};
+	}
+
+	/// Text distance, reduced by how well the cue timings line up
+	fn rank(&self) -> usize {
+		return (self.distance as f64 * (1.0 - TIMING_WEIGHT * self.timing.score)) as usize;
+	}
+
+	/// Rank scaled by the transcript length so it can be compared across episodes
+	fn cost(&self) -> f64 {
+		if self.length == 0 {
+			return 1.0;
+		}
+		return self.rank() as f64 / self.length as f64;
+	}
+
+	/// How alike the two transcripts are, from 0 (nothing in common) to 1 (identical)
+	pub fn similarity(&self) -> f64 {
+		return 1.0 - self.cost();
+	}
+
+	/// How far ahead of the runner-up this match is, from 0 (a coin toss) to 1 (no
+	/// competition at all). Without a runner-up, the match is measured against the
+	/// point where it would have been left unassigned.
+	pub fn confidence(&self, runner_up: Option<&Match>) -> f64 {
+		let similarity = self.similarity();
+		if similarity <= 0.0 {
+			return 0.0;
+		}
+		let runner_up = runner_up
+			.map(Match::similarity)
+			.unwrap_or(1.0 - UNASSIGNED_COST);
+		return ((similarity - runner_up) / similarity).clamp(0.0, 1.0);
+	}
+
+	pub fn describe_similarity(&self) -> String {
+		if self.exact {
+			return format!("{:.2} ({} words apart)", self.similarity(), self.distance);
+		} else {
+			// The distance is only a lower bound, so the similarity can only be lower
+			return format!("< {:.2}", self.similarity());
+		}
+	}
+}
+
+/// Says how many SDH annotations were removed before comparing two transcripts, and
+/// how alike they'd have been otherwise. Returns `None` if there weren't any.
+pub fn describe_sdh(reference: &Transcript, ripped: &Transcript) -> Option<String> {
+	if reference.annotations + ripped.annotations == 0 {
+		return None;
+	}
+	let unstripped = Match::compare(
+		reference.with_annotations(),
+		ripped.with_annotations(),
+		None,
+	);
+	return Some(format!(
+		"removed {} annotation(s) from the reference and {} from the title. Left in, the similarity would be {}",
+		reference.annotations,
+		ripped.annotations,
+		unstripped.describe_similarity()
 	));
 }
 
-pub async fn get_tv_show(tmdb_client: &tmdb_async::Client) -> anyhow::Result<TV> {
+pub fn describe_timing(timing: &TimingAlignment) -> String {
+	if timing.matched == 0 {
+		return String::from("no cues aligned");
+	}
+	return format!(
+		"{:.0}% of cues aligned (offset {:+.2}s, drift {:.4})",
+		timing.score * 100.0,
+		timing.offset_ms / 1000.0,
+		timing.drift
+	);
+}
+
+pub fn describe_runtime(duration: Duration) -> String {
+	let minutes = (duration.as_secs() + 30) / 60;
+	return format!("{}h {:02}m", minutes / 60, minutes % 60);
+}
+
+/// Subtitle contents prepared for comparison
+pub struct Transcript {
+	pub text: String,
+	pub tokens: Vec<u32>,
+	/// Length of the transcript in words
+	pub length: usize,
+	pub cues: Vec<Cue>,
+	/// Number of SDH annotations that were removed
+	pub annotations: usize,
+	/// The transcript as it would have been with its SDH annotations, if any were
+	/// removed, to show what difference that made
+	pub unstripped: Option<Box<Transcript>>,
+}
+impl Transcript {
+	pub fn new(subs: &str) -> Self {
+		if strips_sdh() {
+			let (stripped, annotations) = strip_sdh(subs);
+			if annotations > 0 {
+				let mut transcript = Self::prepare(&stripped);
+				transcript.annotations = annotations;
+				transcript.unstripped = Some(Box::new(Self::prepare(subs)));
+				return transcript;
+			}
+		}
+		return Self::prepare(subs);
+	}
+
+	fn prepare(subs: &str) -> Self {
+		let text = normalize_subtitles(subs);
+		let tokens = tokenize(&text);
+		return Self {
+			length: tokens.len(),
+			tokens,
+			text,
+			cues: parse_srt(subs),
+			annotations: 0,
+			unstripped: None,
+		};
+	}
+
+	/// The transcript with any SDH annotations left in
+	pub fn with_annotations(&self) -> &Transcript {
+		return self.unstripped.as_deref().unwrap_or(self);
+	}
+
+	/// Joins the transcripts of episodes that play one after the other
+	pub fn concat(parts: &[&Transcript]) -> Self {
+		let mut joined = Self {
+			text: String::new(),
+			tokens: Vec::new(),
+			length: 0,
+			cues: Vec::new(),
+			annotations: 0,
+			unstripped: None,
+		};
+		for part in parts {
+			let offset = joined.cues.last().map_or(0, |cue| cue.end);
+			if !joined.text.is_empty() {
+				joined.text.push(' ');
+			}
+			joined.text.push_str(&part.text);
+			joined.tokens.extend(&part.tokens);
+			joined.length += part.length;
+			joined.cues.extend(part.cues.iter().map(|cue| Cue {
+				start: cue.start.saturating_add(offset),
+				end: cue.end.saturating_add(offset),
+				text: cue.text.clone(),
+			}));
+		}
+		joined.annotations = parts.iter().map(|part| part.annotations).sum();
+		if joined.annotations > 0 {
+			let unstripped: Vec<&Transcript> =
+				parts.iter().map(|part| part.with_annotations()).collect();
+			joined.unstripped = Some(Box::new(Self::concat(&unstripped)));
+		}
+		return joined;
+	}
+}
+
+/// Collects the details of a show that naming templates can refer to
+async fn get_show_info(show: &TV, template: &Template) -> anyhow::Result<ShowInfo> {
+	let mut tvdb_id = None;
+	if template.uses(Field::TvdbId) {
+		let tmdb_client = tmdb_async::Client::new(TMDB_API_KEY.clone());
+		tvdb_id = tmdb_client
+			.tv_external_ids(show.id())
+			.await
+			.context("Couldn't get the show's TVDB id")?
+			.tvdb_id();
+	}
+	return Ok(ShowInfo {
+		name: show.name().to_string(),
+		year: show.first_air_date().map(|date| date.year()),
+		tmdb_id: show.id(),
+		tvdb_id,
+	});
+}
+
+/// Finds the show on TMDB. With a hint from the disc's label, a search result with the
+/// same name only has to be confirmed, and otherwise the hint is where the search
+/// starts. Returns whether the hint was confirmed, which makes its season trusted too.
+pub async fn get_tv_show(
+	tmdb_client: &tmdb_async::Client,
+	tmdb_id: Option<u32>,
+	hint: Option<&DiscHint>,
+	trust_label: bool,
+) -> anyhow::Result<(TV, bool)> {
+	if let Some(tmdb_id) = tmdb_id {
+		let show = tmdb_client
+			.tv_by_id(tmdb_id, false, false)
+			.await
+			.context("Couldn't get TV show")?;
+		return Ok((show, false));
+	}
+
+	// A name alone could be any folder, so only a label with a season or disc number
+	// is trusted enough to ask about directly. Nobody is there to answer when running
+	// unattended, so the label is only used if that was asked for.
+	let asked = trust_label || !is_unattended();
+	if let Some(hint) = hint.filter(|hint| asked && hint.is_labelled()) {
+		let titles = tmdb_client.tv_search(&encode(&hint.query), None).await?;
+		let mut same_name = titles
+			.results()
+			.iter()
+			.filter(|title| hint.matches(title.name()));
+		if let (Some(guess), None) = (same_name.next(), same_name.next()) {
+			let name = match guess.first_air_date() {
+				Some(first_air_date) => format!("{} ({})", guess.name(), first_air_date),
+				None => guess.name().to_owned(),
+			};
+			let prompt = format!("Is this disc {}?", hint.describe(&name));
+			let accepted = is_unattended()
+				|| interact(|| {
+					Confirm::with_theme(&*THEME)
+						.with_prompt(prompt)
+						.default(true)
+						.interact()
+				})
+				.await?;
+			if accepted {
+				let show = tmdb_client
+					.tv_by_id(guess.id(), false, false)
+					.await
+					.context("Couldn't get TV show")?;
+				return Ok((show, true));
+			}
+		}
+	}
+
 	// Ask the user for search query
+	ensure_interactive("--tmdb-id")?;
+	let initial_text = hint.map(|hint| hint.query.clone()).unwrap_or_default();
 	let input_title: String = interact(|| {
 		dialoguer::Input::with_theme(&*THEME)
 			.with_prompt("Title")
+			.with_initial_text(initial_text)
 			.interact_text()
 	})
 	.await?;
@@ -219,20 +1450,11 @@ pub async fn get_tv_show(tmdb_client: &tmdb_async::Client) -> anyhow::Result<TV>
 	let title_descriptions: Vec<String> = titles
 		.results()
 		.iter()
-		.map(|title| {
-			match title.first_air_date() {
-				Some(first_air_date) => format!(
-					"{}: {} ({})",
-					title.id(),
-					title.name(),
-					first_air_date
-				),
-				None => format!(
-					"{}: {}",
-					title.id(),
-					title.name(),
-				),
+		.map(|title| match title.first_air_date() {
+			Some(first_air_date) => {
+				format!("{}: {} ({})", title.id(), title.name(), first_air_date)
 			}
+			None => format!("{}: {}", title.id(), title.name(),),
 		})
 		.collect();
 	let selected_title_index: usize = interact(move || {
@@ -243,26 +1465,109 @@ pub async fn get_tv_show(tmdb_client: &tmdb_async::Client) -> anyhow::Result<TV>
 			.interact()
 	})
 	.await?;
-	return Ok(tmdb_client
+	let show = tmdb_client
 		.tv_by_id(titles.results()[selected_title_index].id(), false, false)
 		.await
-		.context("Couldn't get TV show")?);
+		.context("Couldn't get TV show")?;
+	return Ok((show, false));
 }
 
-pub async fn get_episodes_from_user() -> anyhow::Result<Vec<Episode>> {
+pub async fn get_episodes_from_user(
+	args: &TagArgs,
+) -> anyhow::Result<(TV, Option<EpisodeGroup>, Vec<Episode>)> {
 	let tmdb_client = tmdb_async::Client::new(TMDB_API_KEY.clone());
 
-	let selected_title = get_tv_show(&tmdb_client).await?;
+	// Resuming a box set picks the show, episode order and season that it was started
+	// with
+	let resumed = match args.resume {
+		true => Some(select_box_set().await?),
+		false => None,
+	};
+	let tmdb_id = resumed
+		.as_ref()
+		.map(|box_set| box_set.show_id)
+		.or(args.tmdb_id);
+	let group_id = match resumed {
+		Some(ref box_set) => Some(box_set.episode_group.as_deref().unwrap_or("aired")),
+		None => args.episode_group.as_deref(),
+	};
+	let mut season = resumed
+		.as_ref()
+		.map(|box_set| box_set.season)
+		.or(args.season);
+
+	let hint = match tmdb_id {
+		Some(_) => None,
+		None => detect_disc(),
+	};
+	let (selected_title, confirmed) =
+		get_tv_show(&tmdb_client, tmdb_id, hint.as_ref(), args.trust_disc_label).await?;
+	// A season from the label is taken as given once the show it names was confirmed,
+	// and is otherwise only suggested
+	let hinted_season = hint.as_ref().and_then(|hint| hint.season);
+	if confirmed && args.auto_scope.is_none() {
+		season = season.or(hinted_season);
+	}
+	let group = select_episode_group(&tmdb_client, &selected_title, group_id).await?;
+
+	if let Some(ref scope) = args.auto_scope {
+		let seasons: Vec<u32> = list_seasons(&selected_title, group.as_ref())
+			.into_iter()
+			.map(|(number, _)| number)
+			.filter(|number| match scope {
+				Some(NumberRanges(numbers)) => numbers.contains(number),
+				None => *number != 0,
+			})
+			.collect();
+		if seasons.is_empty() {
+			return Err(anyhow!("The show doesn't have any of those seasons"));
+		}
+		let mut episodes = Vec::<Episode>::new();
+		for season_number in seasons {
+			let season =
+				get_season(&tmdb_client, &selected_title, group.as_ref(), season_number).await?;
+			episodes.extend(season.episodes);
+		}
+		return Ok((selected_title, group, episodes));
+	}
+
+	if let Some(season_number) = season {
+		let season =
+			get_season(&tmdb_client, &selected_title, group.as_ref(), season_number).await?;
+		if args.box_set || args.resume {
+			return Ok((selected_title, group, season.episodes));
+		}
+		let Some(NumberRanges(ref episode_numbers)) = args.episodes else {
+			ensure_interactive("--episodes")?;
+			let episodes = select_episodes(season).await?;
+			return Ok((selected_title, group, episodes));
+		};
+		let mut desired_episodes = Vec::<Episode>::new();
+		for episode_number in episode_numbers {
+			let episode = season
+				.episodes
+				.iter()
+				.find(|episode| episode.episode_number == *episode_number)
+				.with_context(|| {
+					format!("Season {season_number} doesn't have an episode {episode_number}")
+				})?;
+			desired_episodes.push(episode.clone());
+		}
+		return Ok((selected_title, group, desired_episodes));
+	}
+	ensure_interactive("--season")?;
 
 	// Get list of desired seasons from user
-	let season_names: Vec<String> = selected_title
-		.seasons()
+	let seasons = list_seasons(&selected_title, group.as_ref());
+	let season_names: Vec<String> = seasons.iter().map(|(_, name)| name.clone()).collect();
+	let hinted: Vec<bool> = seasons
 		.iter()
-		.map(|season| format!("{} ({} episodes)", season.name(), season.episode_count()))
+		.map(|(number, _)| Some(*number) == hinted_season)
 		.collect();
 	let desired_seasons_idx = interact(move || {
 		MultiSelect::with_theme(&*THEME)
 			.items(&season_names)
+			.defaults(&hinted)
 			.with_prompt("Please select the seasons included on this disc")
 			.interact()
 	})
@@ -270,67 +1575,269 @@ pub async fn get_episodes_from_user() -> anyhow::Result<Vec<Episode>> {
 	let mut desired_seasons = Vec::<_>::new();
 	for idx in desired_seasons_idx {
 		desired_seasons.push(
-			tmdb_client
-				.season(
-					selected_title.id(),
-					selected_title.seasons()[idx].season_number(),
-				)
-				.await?,
+			get_season(
+				&tmdb_client,
+				&selected_title,
+				group.as_ref(),
+				seasons[idx].0,
+			)
+			.await?,
 		);
 	}
 
 	// Get list of desired episodes from the user, organized by season
 	let mut desired_episodes = Vec::<Episode>::new();
 	for season in desired_seasons {
-		let episode_names: Vec<String> = season
-			.episodes
+		if args.box_set {
+			desired_episodes.extend(season.episodes);
+		} else {
+			desired_episodes.extend(select_episodes(season).await?);
+		}
+	}
+
+	return Ok((selected_title, group, desired_episodes));
+}
+
+/// A season's episodes, numbered according to the chosen ordering
+struct SeasonEpisodes {
+	name: String,
+	episodes: Vec<Episode>,
+}
+
+/// Picks the episode group that seasons and episodes are numbered by, or `None` for
+/// aired order
+async fn select_episode_group(
+	tmdb_client: &tmdb_async::Client,
+	show: &TV,
+	group_id: Option<&str>,
+) -> anyhow::Result<Option<EpisodeGroup>> {
+	let group_id = match group_id {
+		Some("aired") => return Ok(None),
+		Some(group_id) => group_id.to_owned(),
+		None if is_unattended() => return Ok(None),
+		None => {
+			let groups = tmdb_client
+				.tv_episode_groups(show.id())
+				.await
+				.context("Couldn't get episode groups")?;
+			if groups.is_empty() {
+				return Ok(None);
+			}
+			let mut group_names = vec![String::from("Aired order")];
+			group_names.extend(groups.iter().map(|group| {
+				format!(
+					"{}: {} ({} episodes in {} groups)",
+					group.r#type(),
+					group.name(),
+					group.episode_count(),
+					group.group_count()
+				)
+			}));
+			let selected = interact(move || {
+				Select::with_theme(&*THEME)
+					.items(&group_names)
+					.with_prompt("Which episode order does this disc follow?")
+					.default(0)
+					.interact()
+			})
+			.await?;
+			if selected == 0 {
+				return Ok(None);
+			}
+			groups[selected - 1].id().to_owned()
+		}
+	};
+	let group = tmdb_client
+		.episode_group(&group_id)
+		.await
+		.with_context(|| format!("Couldn't get episode group {group_id}"))?;
+	return Ok(Some(group));
+}
+
+/// Lists the seasons of a show as `(number, description)` pairs
+fn list_seasons(show: &TV, group: Option<&EpisodeGroup>) -> Vec<(u32, String)> {
+	let Some(group) = group else {
+		return show
+			.seasons()
 			.iter()
-			.map(|item| format!("Episode {} - {}", item.episode_number, item.name))
+			.map(|season| {
+				let name = format!("{} ({} episodes)", season.name(), season.episode_count());
+				(season.season_number(), name)
+			})
 			.collect();
-		let desired_episode_indexes = interact(move || {
-			MultiSelect::with_theme(&*THEME)
-				.items(&episode_names)
-				.with_prompt(format!(
-					"Please select the episodes included on this disc from {}",
-					season.name
-				))
+	};
+	return group
+		.groups()
+		.iter()
+		.map(|season| {
+			let name = format!("{} ({} episodes)", season.name(), season.episodes().len());
+			(group_season_number(group, season), name)
+		})
+		.collect();
+}
+
+/// The season number that a group within an episode group stands in for. Groups are
+/// ordered from 0, so they're numbered from 1 like episodes are, unless the first one
+/// holds the specials.
+fn group_season_number(group: &EpisodeGroup, season: &EpisodeSubgroup) -> u32 {
+	let has_specials = group
+		.groups()
+		.iter()
+		.any(|season| season.order() == 0 && season.name().to_lowercase().contains("special"));
+	return if has_specials {
+		season.order()
+	} else {
+		season.order() + 1
+	};
+}
+
+/// Gets the episodes of a season. Within an episode group, each of its groups counts
+/// as a season, and episodes are renumbered to match.
+async fn get_season(
+	tmdb_client: &tmdb_async::Client,
+	show: &TV,
+	group: Option<&EpisodeGroup>,
+	season_number: u32,
+) -> anyhow::Result<SeasonEpisodes> {
+	let Some(group) = group else {
+		let season = tmdb_client
+			.season(show.id(), season_number)
+			.await
+			.with_context(|| format!("Couldn't get season {season_number}"))?;
+		return Ok(SeasonEpisodes {
+			name: season.name,
+			episodes: season.episodes,
+		});
+	};
+	let season = group
+		.groups()
+		.iter()
+		.find(|season| group_season_number(group, season) == season_number)
+		.with_context(|| format!("{} doesn't have a season {season_number}", group.name()))?;
+	let episodes = season
+		.episodes()
+		.iter()
+		.map(|grouped| {
+			let mut episode = grouped.episode().clone();
+			episode.season_number = season_number;
+			episode.episode_number = grouped.order() + 1;
+			episode
+		})
+		.collect();
+	return Ok(SeasonEpisodes {
+		name: season.name().to_string(),
+		episodes,
+	});
+}
+
+/// Asks the user which episodes of a season are included on the disc
+async fn select_episodes(season: SeasonEpisodes) -> anyhow::Result<Vec<Episode>> {
+	let episode_names: Vec<String> = season
+		.episodes
+		.iter()
+		.map(|item| format!("Episode {} - {}", item.episode_number, item.name))
+		.collect();
+	let season_name = season.name.clone();
+	let desired_episode_indexes = interact(move || {
+		MultiSelect::with_theme(&*THEME)
+			.items(&episode_names)
+			.with_prompt(format!(
+				"Please select the episodes included on this disc from {}",
+				season_name
+			))
+			.interact()
+	})
+	.await?;
+	return Ok(desired_episode_indexes
+		.into_iter()
+		.map(|index| season.episodes[index].clone())
+		.collect());
+}
+
+/// Lists the subtitles extracted from the titles on the disc, offering to extract
+/// them first if there aren't any. Returns `None` if the user declined.
+pub async fn get_ripped_subtitles() -> anyhow::Result<Option<Vec<(PathBuf, Transcript)>>> {
+	// Get list of subtitle files without extensions and their contents
+	let files = get_subtitle_files(".").await?;
+	if !files.is_empty() {
+		return Ok(Some(files));
+	}
+	let should_extract = is_unattended()
+		|| interact(|| {
+			Confirm::with_theme(&*THEME)
+				.with_prompt("Subtitles not found. Would you like to extract them?")
 				.interact()
 		})
 		.await?;
-		desired_episodes.extend(
-			desired_episode_indexes
-				.into_iter()
-				.map(|index| season.episodes[index].clone()),
-		);
+	if !should_extract {
+		println!("Cannot continue without subtitles.");
+		return Ok(None);
 	}
-
-	return Ok(desired_episodes);
+	// Extraction may need to prompt for its own things, so it can't run while
+	// holding onto the console.
+	extract_subtitles(false, None).await?;
+	return Ok(Some(get_subtitle_files(".").await?));
 }
 
-async fn get_subtitle_files(location: impl AsRef<Path>) -> anyhow::Result<Vec<(PathBuf, String)>> {
+async fn get_subtitle_files(
+	location: impl AsRef<Path>,
+) -> anyhow::Result<Vec<(PathBuf, Transcript)>> {
 	let mut files_iter = fs::read_dir(location).await?;
-	let mut files = Vec::<(PathBuf, String)>::new();
+	let mut files = Vec::<(PathBuf, Transcript)>::new();
 	while let Some(file) = files_iter.next_entry().await? {
 		let path = file.path();
+		// Corrected subtitles are only written for looking over
+		if path.to_string_lossy().ends_with(".corrected.srt") {
+			continue;
+		}
 		if let Some(Some("srt")) = path.extension().map(|ext| ext.to_str()) {
 			let mut contents = String::new();
 			File::open(&path)
 				.await?
 				.read_to_string(&mut contents)
 				.await?;
-			files.push((path.with_extension(""), strip_subtitles(&contents)));
+			files.push((path.with_extension(""), Transcript::new(&contents)));
 		}
 	}
+	files.sort_unstable_by(|a, b| a.0.cmp(&b.0));
 	return Ok(files);
 }
 
-/// Strips symbols from subtitles that may cause issues during comparison
-pub fn strip_subtitles(subs: &str) -> String {
-	let intermediate = regex!(
-		r"(?:<\s*[^>]*>|<\s*/\s*a>)|(?:^.*-->.*$|^[0-9]+$|[^a-zA-Z0-9 ?\.,!\n]|^\s*-*\s*|\r)"m
-	)
-	.replace_all(subs, "");
-	return regex!(r"[\n ]{1,}")
-		.replace_all(&intermediate, " ")
-		.into_owned();
+/// Files that go along with a title and are cleaned up when it's renamed: its
+/// subtitles, and the corrected ones if they were written
+pub fn sidecars(file_path: &Path) -> Vec<PathBuf> {
+	let mut sidecars = vec![file_path.with_extension("srt")];
+	let corrected = corrected_path(file_path);
+	if corrected.exists() {
+		sidecars.push(corrected);
+	}
+	return sidecars;
+}
+
+/// Gets the runtime of an mkv file, if it can be read
+pub fn get_duration(file: &Path) -> Option<Duration> {
+	return matroska::open(file).ok()?.info.duration;
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn concatenated_cues_follow_on() {
+		let first = Transcript::new("1\n00:00:01,000 --> 00:00:02,000\nHello there.\n");
+		let second = Transcript::new("1\n00:00:03,000 --> 00:00:04,000\nGoodbye.\n");
+		let joined = Transcript::concat(&[&first, &second]);
+		let times: Vec<(u32, u32)> = joined.cues.iter().map(|cue| (cue.start, cue.end)).collect();
+		assert_eq!(times, [(1000, 2000), (5000, 6000)]);
+		assert_eq!(joined.length, first.length + second.length);
+	}
+
+	#[test]
+	fn concatenating_damaged_timings_does_not_overflow() {
+		let damaged = Transcript::new("1\n999:00:00,000 --> 999:00:01,000\nHello there.\n");
+		let joined = Transcript::concat(&[&damaged, &damaged, &damaged]);
+		assert_eq!(joined.cues.len(), 3);
+		assert_eq!(joined.cues[2].end, u32::MAX);
+	}
 }
@@ -0,0 +1,337 @@
+use std::{collections::BTreeMap, io::ErrorKind, path::PathBuf};
+
+use anyhow::{anyhow, Context};
+use dialoguer::Select;
+use serde::{Deserialize, Serialize};
+use tmdb_async::{Episode, EpisodeGroup, TV};
+use tokio::fs;
+
+use crate::{
+	interact::{ensure_interactive, interact},
+	journal::unix_time,
+	language::language,
+	plan::PlanEntry,
+	THEME,
+};
+
+/// Progress through a season that's spread over several discs, kept between runs so
+/// each disc only has to be compared against the episodes that are still missing
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BoxSet {
+	pub show_id: u32,
+	pub show_name: String,
+	/// Episode group the season is numbered by, or `None` for aired order
+	pub episode_group: Option<String>,
+	pub season: u32,
+	/// TMDB ids of every episode in the season, in order
+	pub episodes: Vec<u32>,
+	/// Episodes that were tagged from an earlier disc
+	#[serde(default)]
+	pub tagged: BTreeMap<u32, TaggedEpisode>,
+	/// Seconds since the unix epoch
+	#[serde(default)]
+	pub updated: u64,
+}
+
+/// Which box set a plan updates when it's applied
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BoxSetKey {
+	pub show_id: u32,
+	pub episode_group: Option<String>,
+	pub season: u32,
+}
+
+/// Where an episode of a box set ended up
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TaggedEpisode {
+	pub file: PathBuf,
+	/// Folder the disc was ripped to
+	pub disc: PathBuf,
+}
+
+/// Box sets are kept in here, in a file per show and season, with a folder of the
+/// same name holding their reference subtitles
+fn box_set_dir() -> anyhow::Result<PathBuf> {
+	let data_dir = dirs::data_dir().ok_or_else(|| anyhow!("Couldn't find a data folder"))?;
+	return Ok(data_dir.join("plex-autotagger").join("box-sets"));
+}
+
+impl BoxSet {
+	/// Picks up where an earlier disc of the season left off, or starts a new box set.
+	/// Episodes whose files have since been moved or undone are looked for again.
+	pub async fn open(
+		show: &TV,
+		group: Option<&EpisodeGroup>,
+		season: u32,
+		episodes: &[&Episode],
+	) -> anyhow::Result<Self> {
+		let mut box_set = BoxSet {
+			show_id: show.id(),
+			show_name: show.name().to_string(),
+			episode_group: group.map(|group| group.id().to_owned()),
+			season,
+			episodes: episodes.iter().map(|episode| episode.id).collect(),
+			tagged: BTreeMap::new(),
+			updated: unix_time().as_secs(),
+		};
+		let path = box_set.path()?;
+		match fs::read_to_string(&path).await {
+			Ok(contents) => {
+				let earlier: BoxSet = serde_json::from_str(&contents)
+					.with_context(|| format!("{} is not a valid box set", path.display()))?;
+				box_set.tagged = earlier.tagged;
+			}
+			Err(err) if err.kind() == ErrorKind::NotFound => {}
+			Err(err) => {
+				return Err(err).with_context(|| format!("Couldn't read {}", path.display()))
+			}
+		}
+		let mut missing = Vec::<u32>::new();
+		for (episode_id, tagged) in &box_set.tagged {
+			if !fs::try_exists(&tagged.file).await? {
+				missing.push(*episode_id);
+			}
+		}
+		for episode_id in missing {
+			let tagged = box_set.tagged.remove(&episode_id).unwrap();
+			println!(
+				"{:?} is gone, so its episode will be looked for again",
+				tagged.file
+			);
+		}
+		return Ok(box_set);
+	}
+
+	/// Reads a box set that was saved by `tag`
+	pub async fn load(key: &BoxSetKey) -> anyhow::Result<Self> {
+		let path = box_set_dir()?.join(format!("{}.json", key.name()));
+		let contents = fs::read_to_string(&path)
+			.await
+			.with_context(|| format!("Couldn't read {}", path.display()))?;
+		return serde_json::from_str(&contents)
+			.with_context(|| format!("{} is not a valid box set", path.display()));
+	}
+
+	pub fn key(&self) -> BoxSetKey {
+		return BoxSetKey {
+			show_id: self.show_id,
+			episode_group: self.episode_group.clone(),
+			season: self.season,
+		};
+	}
+
+	/// Lists the box sets that still have episodes missing, most recent first
+	pub async fn incomplete() -> anyhow::Result<Vec<Self>> {
+		let folder = box_set_dir()?;
+		let mut listing = match fs::read_dir(&folder).await {
+			Ok(listing) => listing,
+			Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+			Err(err) => {
+				return Err(err).with_context(|| format!("Couldn't read {}", folder.display()))
+			}
+		};
+		let mut box_sets = Vec::<BoxSet>::new();
+		while let Some(item) = listing.next_entry().await? {
+			let path = item.path();
+			if path.extension().is_none_or(|extension| extension != "json") {
+				continue;
+			}
+			let contents = fs::read_to_string(&path)
+				.await
+				.with_context(|| format!("Couldn't read {}", path.display()))?;
+			let box_set: BoxSet = serde_json::from_str(&contents)
+				.with_context(|| format!("{} is not a valid box set", path.display()))?;
+			if !box_set.is_complete() {
+				box_sets.push(box_set);
+			}
+		}
+		box_sets.sort_unstable_by_key(|box_set| std::cmp::Reverse(box_set.updated));
+		return Ok(box_sets);
+	}
+
+	pub async fn save(&mut self) -> anyhow::Result<()> {
+		self.updated = unix_time().as_secs();
+		let path = self.path()?;
+		fs::create_dir_all(path.parent().unwrap())
+			.await
+			.with_context(|| format!("Couldn't create {}", path.parent().unwrap().display()))?;
+		fs::write(&path, serde_json::to_string_pretty(self)?)
+			.await
+			.with_context(|| format!("Couldn't write {}", path.display()))?;
+		return Ok(());
+	}
+
+	fn path(&self) -> anyhow::Result<PathBuf> {
+		return Ok(box_set_dir()?.join(format!("{}.json", self.name())));
+	}
+
+	fn name(&self) -> String {
+		return self.key().name();
+	}
+
+	/// Discs in different languages can share a box set, so references are kept apart
+	/// by their language
+	fn reference_path(&self, episode_id: u32) -> anyhow::Result<PathBuf> {
+		return Ok(box_set_dir()?
+			.join(self.name())
+			.join(format!("{episode_id}.{}.srt", language().code)));
+	}
+
+	pub fn contains(&self, episode_id: u32) -> bool {
+		return self.episodes.contains(&episode_id);
+	}
+
+	pub fn is_tagged(&self, episode_id: u32) -> bool {
+		return self.tagged.contains_key(&episode_id);
+	}
+
+	/// The number of an episode, going by where it is in the season
+	pub fn position(&self, episode_id: u32) -> Option<u32> {
+		return self
+			.episodes
+			.iter()
+			.position(|id| *id == episode_id)
+			.map(|idx| idx as u32 + 1);
+	}
+
+	pub fn is_complete(&self) -> bool {
+		return self
+			.episodes
+			.iter()
+			.all(|episode_id| self.tagged.contains_key(episode_id));
+	}
+
+	/// Reference subtitles saved by an earlier disc, if there are any
+	pub async fn cached_reference(&self, episode_id: u32) -> Option<String> {
+		return fs::read_to_string(self.reference_path(episode_id).ok()?)
+			.await
+			.ok();
+	}
+
+	pub async fn cache_reference(&self, episode_id: u32, subtitles: &str) -> anyhow::Result<()> {
+		let path = self.reference_path(episode_id)?;
+		fs::create_dir_all(path.parent().unwrap())
+			.await
+			.with_context(|| format!("Couldn't create {}", path.parent().unwrap().display()))?;
+		fs::write(&path, subtitles)
+			.await
+			.with_context(|| format!("Couldn't write {}", path.display()))?;
+		return Ok(());
+	}
+
+	/// Remembers the episodes in files that were renamed
+	pub fn record(&mut self, entries: &[&PlanEntry]) -> anyhow::Result<()> {
+		for entry in entries.iter().filter(|entry| entry.extra.is_none()) {
+			let file = std::path::absolute(&entry.target)?;
+			// The disc was ripped to the folder the file came from
+			let disc = match entry.source.parent() {
+				Some(folder) if !folder.as_os_str().is_empty() => std::path::absolute(folder)?,
+				_ => std::env::current_dir()?,
+			};
+			for episode_id in &entry.episode_ids {
+				if self.contains(*episode_id) {
+					self.tagged.insert(
+						*episode_id,
+						TaggedEpisode {
+							file: file.clone(),
+							disc: disc.clone(),
+						},
+					);
+				}
+			}
+		}
+		return Ok(());
+	}
+
+	/// Says how far along the season is, listing the episodes that are still missing by
+	/// their number
+	pub fn describe_progress(&self, episode_number: impl Fn(u32) -> Option<u32>) -> String {
+		let tagged = self
+			.episodes
+			.iter()
+			.filter(|episode_id| self.is_tagged(**episode_id))
+			.count();
+		if tagged == self.episodes.len() {
+			return format!(
+				"{} season {}: complete, all {} episodes are tagged",
+				self.show_name, self.season, tagged
+			);
+		}
+		let missing: Vec<u32> = self
+			.episodes
+			.iter()
+			.filter(|episode_id| !self.is_tagged(**episode_id))
+			.filter_map(|episode_id| episode_number(*episode_id))
+			.collect();
+		return format!(
+			"{} season {}: incomplete, {} of {} episodes are tagged, still missing {}",
+			self.show_name,
+			self.season,
+			tagged,
+			self.episodes.len(),
+			describe_numbers(&missing)
+		);
+	}
+}
+
+impl BoxSetKey {
+	fn name(&self) -> String {
+		return format!(
+			"{}-{}-s{:02}",
+			self.show_id,
+			self.episode_group.as_deref().unwrap_or("aired"),
+			self.season
+		);
+	}
+}
+
+/// Asks which box set to continue with, unless there's only one
+pub async fn select_box_set() -> anyhow::Result<BoxSet> {
+	let mut box_sets = BoxSet::incomplete().await?;
+	if box_sets.len() <= 1 {
+		return box_sets
+			.pop()
+			.ok_or_else(|| anyhow!("There aren't any box sets to resume"));
+	}
+	ensure_interactive("--box-set with --tmdb-id and --season instead of --resume")?;
+	let names: Vec<String> = box_sets
+		.iter()
+		.map(|box_set| {
+			format!(
+				"{} season {} ({} of {} episodes tagged)",
+				box_set.show_name,
+				box_set.season,
+				box_set.tagged.len(),
+				box_set.episodes.len()
+			)
+		})
+		.collect();
+	let selected = interact(move || {
+		Select::with_theme(&*THEME)
+			.items(&names)
+			.with_prompt("Which box set is this disc from?")
+			.default(0)
+			.interact()
+	})
+	.await?;
+	return Ok(box_sets.swap_remove(selected));
+}
+
+/// Lists numbers compactly, such as "1-6, 8"
+fn describe_numbers(numbers: &[u32]) -> String {
+	let mut runs = Vec::<(u32, u32)>::new();
+	for number in numbers {
+		match runs.last_mut() {
+			Some((_, last)) if *last + 1 == *number => *last = *number,
+			_ => runs.push((*number, *number)),
+		}
+	}
+	return runs
+		.iter()
+		.map(|(first, last)| match first == last {
+			true => first.to_string(),
+			false => format!("{first}-{last}"),
+		})
+		.collect::<Vec<String>>()
+		.join(", ");
+}
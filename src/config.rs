@@ -0,0 +1,56 @@
+use std::{path::PathBuf, sync::OnceLock};
+
+use anyhow::Context;
+use serde::Deserialize;
+
+use crate::{normalize::SdhAnnotations, ocr_correction::OcrCorrection};
+
+/// Settings that apply to every run, read from `config.toml` in the user's config
+/// directory, or from the file named by `AUTOTAGGER_CONFIG`. Command line arguments
+/// take precedence over anything set here.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+	/// Naming template or preset used when `--template` isn't given
+	pub template: Option<String>,
+	/// Where duplicates and play-all titles are moved when `--quarantine` isn't given
+	pub quarantine: Option<PathBuf>,
+	/// Language of the subtitles when `--language` isn't given, such as "de"
+	pub language: Option<String>,
+	/// What to do with SDH annotations when `--sdh` isn't given
+	pub sdh: Option<SdhAnnotations>,
+	/// What to do about OCR errors when `--ocr-correction` isn't given
+	pub ocr_correction: Option<OcrCorrection>,
+}
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+fn config_path() -> Option<PathBuf> {
+	if let Some(path) = std::env::var_os("AUTOTAGGER_CONFIG") {
+		return Some(PathBuf::from(path));
+	}
+	return Some(
+		dirs::config_dir()?
+			.join("plex-autotagger")
+			.join("config.toml"),
+	);
+}
+
+/// Reads the config file, if there is one. Must be called before `config()`.
+pub fn load_config() -> anyhow::Result<()> {
+	let config = match config_path() {
+		Some(path) if path.exists() => {
+			let contents = std::fs::read_to_string(&path)
+				.with_context(|| format!("Couldn't read {}", path.display()))?;
+			toml::from_str(&contents)
+				.with_context(|| format!("{} is not a valid config file", path.display()))?
+		}
+		_ => Config::default(),
+	};
+	let _ = CONFIG.set(config);
+	return Ok(());
+}
+
+pub fn config() -> &'static Config {
+	return CONFIG.get_or_init(Config::default);
+}
@@ -0,0 +1,195 @@
+use std::path::Path;
+
+use lazy_regex::regex_captures;
+
+/// What a disc seems to be, going by its volume label or the name MakeMKV gave it,
+/// such as "FRIENDS_S1_D2"
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiscHint {
+	/// Name of the show, to search TMDB for
+	pub query: String,
+	pub season: Option<u32>,
+	pub disc: Option<u32>,
+}
+
+impl DiscHint {
+	/// Whether the label looked like a disc of a TV show, rather than just any name
+	pub fn is_labelled(&self) -> bool {
+		return self.season.is_some() || self.disc.is_some();
+	}
+
+	/// Whether a search result has the same name as the one on the disc
+	pub fn matches(&self, name: &str) -> bool {
+		return simplify(name) == simplify(&self.query);
+	}
+
+	pub fn describe(&self, show: &str) -> String {
+		let mut description = String::from(show);
+		if let Some(season) = self.season {
+			description.push_str(&format!(", season {season}"));
+		}
+		if let Some(disc) = self.disc {
+			description.push_str(&format!(", disc {disc}"));
+		}
+		return description;
+	}
+}
+
+/// Looks for hints in the name of the working directory, which MakeMKV names after
+/// the volume label, and then in the titles of the mkv files in it. A hint with a
+/// season or disc number is preferred over one that's only a name.
+pub fn detect_disc() -> Option<DiscHint> {
+	let mut labels = Vec::<String>::new();
+	if let Some(name) = std::env::current_dir().ok().and_then(|dir| {
+		dir.file_name()
+			.map(|name| name.to_string_lossy().into_owned())
+	}) {
+		labels.push(name);
+	}
+	let mut files: Vec<_> = std::fs::read_dir(".")
+		.ok()?
+		.filter_map(|item| item.ok())
+		.map(|item| item.path())
+		.filter(|path| path.extension().is_some_and(|extension| extension == "mkv"))
+		.collect();
+	files.sort_unstable();
+	labels.extend(files.iter().filter_map(|file| mkv_title(file)));
+
+	let hints: Vec<DiscHint> = labels
+		.iter()
+		.filter_map(|label| parse_label(label))
+		.collect();
+	return hints
+		.iter()
+		.find(|hint| hint.is_labelled())
+		.or(hints.first())
+		.cloned();
+}
+
+fn mkv_title(file: &Path) -> Option<String> {
+	return matroska::open(file).ok()?.info.title;
+}
+
+/// Splits a label into the name of the show and any season and disc numbers, such as
+/// "THE_OFFICE_SEASON_2_DISC_1" or "Lost.S3D4". Anything after the season or disc is
+/// left out of the name.
+pub fn parse_label(label: &str) -> Option<DiscHint> {
+	let tokens: Vec<String> = label
+		.split(|c: char| c == '_' || c == '.' || c == '-' || c.is_whitespace())
+		.filter(|token| !token.is_empty())
+		.map(|token| token.to_uppercase())
+		.collect();
+	let mut name = Vec::<&str>::new();
+	let mut season = None;
+	let mut disc = None;
+	let mut idx = 0;
+	while idx < tokens.len() {
+		let token = tokens[idx].as_str();
+		let next = tokens.get(idx + 1).and_then(|token| parse_number(token));
+		if let Some((_, s, d)) = regex_captures!(r"^S(\d{1,2})(?:D(\d{1,2}))?$", token) {
+			season = s.parse().ok();
+			disc = d.parse().ok().or(disc);
+		} else if let Some((_, s)) = regex_captures!(r"^(?:SEASON|SERIES)(\d{1,2})$", token) {
+			season = s.parse().ok();
+		} else if let Some((_, d)) = regex_captures!(r"^(?:D|DISC|DISK)(\d{1,2})$", token) {
+			disc = d.parse().ok();
+		} else if matches!(token, "SEASON" | "SERIES") && next.is_some() {
+			season = next;
+			idx += 1;
+		} else if matches!(token, "D" | "DISC" | "DISK") && next.is_some() {
+			disc = next;
+			idx += 1;
+		} else if season.is_none() && disc.is_none() {
+			name.push(token);
+		}
+		idx += 1;
+	}
+	if name.is_empty() {
+		return None;
+	}
+	let query = name
+		.iter()
+		.map(|word| label_case(word))
+		.collect::<Vec<String>>()
+		.join(" ");
+	return Some(DiscHint {
+		query,
+		season,
+		disc,
+	});
+}
+
+fn parse_number(token: &str) -> Option<u32> {
+	const WORDS: [&str; 10] = [
+		"ONE", "TWO", "THREE", "FOUR", "FIVE", "SIX", "SEVEN", "EIGHT", "NINE", "TEN",
+	];
+	if let Some(idx) = WORDS.iter().position(|word| *word == token) {
+		return Some(idx as u32 + 1);
+	}
+	return token.parse().ok();
+}
+
+/// Labels are usually in capitals, which look odd in a search box
+fn label_case(word: &str) -> String {
+	let mut chars = word.chars();
+	return match chars.next() {
+		Some(first) => first
+			.to_uppercase()
+			.chain(chars.flat_map(char::to_lowercase))
+			.collect(),
+		None => String::new(),
+	};
+}
+
+/// Reduces a name to its letters and digits, so "The Office (US)" and "THE_OFFICE_US"
+/// compare equal
+fn simplify(name: &str) -> String {
+	return name
+		.chars()
+		.filter(|c| c.is_alphanumeric())
+		.flat_map(char::to_lowercase)
+		.collect();
+}
+
+#[cfg(test)]
+mod tests {
+	// Parses the kinds of volume labels found on TV box sets.
+
+	use super::*;
+
+	fn hint(query: &str, season: Option<u32>, disc: Option<u32>) -> Option<DiscHint> {
+		return Some(DiscHint {
+			query: query.to_owned(),
+			season,
+			disc,
+		});
+	}
+
+	#[test]
+	fn parses_labels() {
+		let cases = [
+			("FRIENDS_S1_D2", hint("Friends", Some(1), Some(2))),
+			("Lost.S3D4", hint("Lost", Some(3), Some(4))),
+			(
+				"THE_OFFICE_SEASON_2_DISC_1",
+				hint("The Office", Some(2), Some(1)),
+			),
+			("THE_WIRE_SEASON2", hint("The Wire", Some(2), None)),
+			("SOPRANOS_SERIES_4", hint("Sopranos", Some(4), None)),
+			("BLACKADDER_DISC_1", hint("Blackadder", None, Some(1))),
+			("SEINFELD D3", hint("Seinfeld", None, Some(3))),
+			(
+				"FIREFLY_SEASON_ONE_DISC_TWO",
+				hint("Firefly", Some(1), Some(2)),
+			),
+			// Anything after the season or disc isn't part of the name
+			("DEADWOOD_S2_D1_EXTRAS", hint("Deadwood", Some(2), Some(1))),
+			("twin-peaks", hint("Twin Peaks", None, None)),
+			("S1_D1", None),
+			("", None),
+		];
+		for (label, expected) in cases {
+			assert_eq!(parse_label(label), expected, "{label:?}");
+		}
+	}
+}
@@ -0,0 +1,107 @@
+use std::time::Duration;
+
+use clap::ValueEnum;
+
+use crate::{
+	autotagger::Transcript,
+	edit_distance::bounded_distance,
+	similarity::{MinHashEngine, SimilarityEngine},
+};
+
+/// Largest edit distance between two titles, relative to the longer of them, for them
+/// to count as copies of each other. Alternate angles have identical subtitles, and
+/// OCR errors account for most of the rest.
+const DUPLICATE_COST: f64 = 0.1;
+
+/// Pairs whose estimated overlap is below this aren't worth comparing in full
+const MIN_ESTIMATE: f64 = 0.5;
+
+/// Titles with less dialogue than this, such as menus and logos, look alike without
+/// being copies of each other
+const MIN_WORDS: usize = 50;
+
+/// What to do with titles that are copies of another
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum DuplicateAction {
+	/// Leave them where they are
+	Report,
+	/// Move them to the quarantine folder
+	Quarantine,
+	/// Move them to the trash, where they're kept until the journal is pruned
+	Delete,
+}
+
+/// A title that's a copy of another, and won't be matched against anything
+#[derive(Debug, Clone, Copy)]
+pub struct Duplicate {
+	/// Index of the title that was kept in its place
+	pub original: usize,
+	/// How alike its subtitles are to the rest of its cluster, from 0 to 1
+	pub similarity: f64,
+}
+
+/// Groups titles whose subtitles are nearly the same, such as alternate angles or
+/// seamless branches of the same episode. Only groups of two or more are returned.
+pub fn cluster_duplicates(transcripts: &[&Transcript]) -> Vec<Vec<(usize, f64)>> {
+	let texts: Vec<&str> = transcripts
+		.iter()
+		.map(|transcript| transcript.text.as_str())
+		.collect();
+	let estimates = MinHashEngine::default().similarities(&texts, &texts);
+
+	// Each title starts out in a cluster of its own, and clusters are merged whenever
+	// two of their members turn out to be copies
+	let mut cluster_of: Vec<usize> = (0..transcripts.len()).collect();
+	let mut similarities = vec![1.0; transcripts.len()];
+	for a in 0..transcripts.len() {
+		for b in a + 1..transcripts.len() {
+			if cluster_of[a] == cluster_of[b]
+				|| estimates[a][b] < MIN_ESTIMATE
+				|| transcripts[a].length.min(transcripts[b].length) < MIN_WORDS
+			{
+				continue;
+			}
+			let length = transcripts[a].length.max(transcripts[b].length);
+			let bound = (length as f64 * DUPLICATE_COST) as usize;
+			let Some(distance) =
+				bounded_distance(&transcripts[a].tokens, &transcripts[b].tokens, bound)
+			else {
+				continue;
+			};
+			let similarity = 1.0 - distance as f64 / length as f64;
+			for idx in [a, b] {
+				similarities[idx] = f64::min(similarities[idx], similarity);
+			}
+			let (from, to) = (cluster_of[b], cluster_of[a]);
+			for cluster in cluster_of.iter_mut().filter(|cluster| **cluster == from) {
+				*cluster = to;
+			}
+		}
+	}
+
+	let mut clusters = Vec::<Vec<(usize, f64)>>::new();
+	for cluster in 0..transcripts.len() {
+		let members: Vec<(usize, f64)> = (0..transcripts.len())
+			.filter(|idx| cluster_of[*idx] == cluster)
+			.map(|idx| (idx, similarities[idx]))
+			.collect();
+		if members.len() > 1 {
+			clusters.push(members);
+		}
+	}
+	return clusters;
+}
+
+/// Picks which title of a cluster to keep: the longest one, and then the largest, as
+/// that's the one most likely to be complete
+pub fn pick_original(
+	cluster: &[(usize, f64)],
+	durations: &[Option<Duration>],
+	sizes: &[u64],
+) -> usize {
+	return cluster
+		.iter()
+		.map(|(idx, _)| *idx)
+		.max_by_key(|idx| (durations[*idx], sizes[*idx]))
+		.unwrap();
+}
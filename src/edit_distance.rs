@@ -0,0 +1,353 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use lazy_static::lazy_static;
+
+lazy_static! {
+	/// Every word seen so far, so transcripts can be compared as sequences of ids
+	static ref VOCABULARY: Mutex<HashMap<String, u32>> = Mutex::new(HashMap::new());
+}
+
+/// Number of rows handled by each block of the bit-parallel algorithm
+const WORD_SIZE: usize = 64;
+
+/// How often (in columns) to check whether the bound can still be met
+const BOUND_CHECK_INTERVAL: usize = 64;
+
+/// Splits a transcript into words, mapping each one to an id that is shared by every
+/// transcript in the process.
+pub fn tokenize(text: &str) -> Vec<u32> {
+	let mut vocabulary = VOCABULARY.lock().unwrap();
+	return text
+		.split_whitespace()
+		.map(|word| {
+			word.trim_matches(|c: char| !c.is_alphanumeric())
+				.to_lowercase()
+		})
+		.filter(|word| !word.is_empty())
+		.map(|word| {
+			let next_id = vocabulary.len() as u32;
+			*vocabulary.entry(word).or_insert(next_id)
+		})
+		.collect();
+}
+
+/// Computes the edit distance between two token sequences, giving up as soon as it's
+/// clear the result will be larger than `bound`.
+///
+/// This is Myers' bit-parallel algorithm in the blocked form described by Hyyrö,
+/// which handles 64 tokens of `pattern` per machine word. Only the diagonal band
+/// that could still produce a distance within `bound` is computed, so a tight bound
+/// makes comparisons against poor candidates considerably cheaper.
+pub fn bounded_distance(pattern: &[u32], text: &[u32], bound: usize) -> Option<usize> {
+	let rows = pattern.len();
+	let columns = text.len();
+	// The distance can never be more than this, so larger bounds are meaningless
+	let bound = bound.min(rows.max(columns));
+	if rows.abs_diff(columns) > bound {
+		return None;
+	}
+	if rows == 0 || columns == 0 {
+		return Some(rows.max(columns));
+	}
+
+	let block_count = rows.div_ceil(WORD_SIZE);
+	let last_row_bit = 1u64 << ((rows - 1) % WORD_SIZE);
+	let mut peq = HashMap::<u32, Vec<u64>>::new();
+	for (row, token) in pattern.iter().enumerate() {
+		peq.entry(*token).or_insert_with(|| vec![0; block_count])[row / WORD_SIZE] |=
+			1 << (row % WORD_SIZE);
+	}
+
+	// Any alignment within the bound stays inside this band of diagonals, measured as
+	// `row - column`. Cells outside of it are never computed exactly.
+	let bound = bound as isize;
+	let skew = rows as isize - columns as isize;
+	let band_low = (skew - bound).div_euclid(2);
+	let band_high = (skew + bound).div_euclid(2) + 1;
+	let block_rows = |block: usize| -> usize { WORD_SIZE.min(rows - block * WORD_SIZE) };
+
+	let mut blocks = vec![Block::default(); block_count];
+	blocks[0] = Block::new(block_rows(0));
+	let mut first_block = 0;
+	let mut last_block = 0;
+	let zeros = vec![0u64; block_count];
+	for (column, token) in text.iter().enumerate() {
+		let column = column + 1;
+
+		// Bring in blocks as the band reaches them. Assuming every cell is one more
+		// than the one above it overestimates cells outside the band, which is safe.
+		let lowest_row = (column as isize + band_high).clamp(1, rows as isize) as usize;
+		while last_block + 1 < block_count && (last_block + 1) * WORD_SIZE < lowest_row {
+			last_block += 1;
+			let mut block = Block::new(block_rows(last_block));
+			block.score = blocks[last_block - 1].score + block_rows(last_block) as isize;
+			blocks[last_block] = block;
+		}
+		// ...and drop them once the band has moved past them entirely
+		let highest_row = column as isize + band_low;
+		while first_block < last_block && (((first_block + 1) * WORD_SIZE) as isize) < highest_row {
+			first_block += 1;
+		}
+
+		let equalities = peq.get(token).unwrap_or(&zeros);
+		let mut carry = 1;
+		for block in first_block..=last_block {
+			let high_bit = if block == block_count - 1 {
+				last_row_bit
+			} else {
+				1 << (WORD_SIZE - 1)
+			};
+			carry = blocks[block].advance(equalities[block], carry, high_bit);
+		}
+
+		if column % BOUND_CHECK_INTERVAL == 0 {
+			// A cell can only lead to a final distance within the bound if its score
+			// plus the gap left between the remaining rows and columns is within it.
+			let remaining = (columns - column) as isize;
+			let within_bound = |row: usize, score: isize| -> bool {
+				return score + (rows as isize - row as isize - remaining).abs() <= bound;
+			};
+			let reachable = (first_block == 0 && within_bound(0, column as isize))
+				|| (first_block..=last_block).any(|block| {
+					blocks[block].cells_within(block * WORD_SIZE + block_rows(block), within_bound)
+				});
+			if !reachable {
+				return None;
+			}
+		}
+	}
+
+	let distance = blocks[block_count - 1].score;
+	if distance > bound || last_block != block_count - 1 {
+		return None;
+	}
+	return Some(distance as usize);
+}
+
+/// Column state for a block of up to 64 rows
+#[derive(Debug, Clone, Copy, Default)]
+struct Block {
+	/// Rows where the score increases by one going down the column
+	positive: u64,
+	/// Rows where the score decreases by one going down the column
+	negative: u64,
+	/// Score at the bottom row of the block
+	score: isize,
+}
+impl Block {
+	fn new(rows: usize) -> Self {
+		return Self {
+			positive: u64::MAX,
+			negative: 0,
+			score: rows as isize,
+		};
+	}
+
+	/// Moves the block forward by one column, given the horizontal difference coming
+	/// in from the block above, and returns the difference leaving its bottom row.
+	fn advance(&mut self, equalities: u64, carry_in: isize, high_bit: u64) -> isize {
+		let mut equalities = equalities;
+		let vertical = equalities | self.negative;
+		if carry_in < 0 {
+			equalities |= 1;
+		}
+		let horizontal = (((equalities & self.positive).wrapping_add(self.positive))
+			^ self.positive)
+			| equalities;
+		let mut horizontal_positive = self.negative | !(horizontal | self.positive);
+		let mut horizontal_negative = self.positive & horizontal;
+
+		let mut carry_out = 0;
+		if horizontal_positive & high_bit != 0 {
+			carry_out = 1;
+		}
+		if horizontal_negative & high_bit != 0 {
+			carry_out = -1;
+		}
+
+		horizontal_positive <<= 1;
+		horizontal_negative <<= 1;
+		if carry_in < 0 {
+			horizontal_negative |= 1;
+		} else if carry_in > 0 {
+			horizontal_positive |= 1;
+		}
+		self.positive = horizontal_negative | !(vertical | horizontal_positive);
+		self.negative = horizontal_positive & vertical;
+		self.score += carry_out;
+		return carry_out;
+	}
+
+	/// Walks up the block from its bottom row (`bottom_row`, counted from 1),
+	/// reporting whether `predicate` holds for any row and its score.
+	fn cells_within(&self, bottom_row: usize, predicate: impl Fn(usize, isize) -> bool) -> bool {
+		let rows = (bottom_row - 1) % WORD_SIZE + 1;
+		let mut score = self.score;
+		for offset in (0..rows).rev() {
+			let row = bottom_row - (rows - 1 - offset);
+			if predicate(row, score) {
+				return true;
+			}
+			if self.positive & (1 << offset) != 0 {
+				score -= 1;
+			} else if self.negative & (1 << offset) != 0 {
+				score += 1;
+			}
+		}
+		return false;
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	// Checks the blocked, banded bit-parallel distance against a plain dynamic
+	// programming implementation, on random token sequences long enough to span several
+	// blocks, and with bounds right at the edge of the band.
+
+	use super::*;
+	use crate::xorshift::Rng;
+
+	/// Textbook Levenshtein distance, one row at a time
+	fn plain_distance(pattern: &[u32], text: &[u32]) -> usize {
+		let mut previous: Vec<usize> = (0..=text.len()).collect();
+		for (row, token) in pattern.iter().enumerate() {
+			let mut current = vec![row + 1; text.len() + 1];
+			for (column, other) in text.iter().enumerate() {
+				let substitution = previous[column] + usize::from(token != other);
+				current[column + 1] = substitution
+					.min(previous[column + 1] + 1)
+					.min(current[column] + 1);
+			}
+			previous = current;
+		}
+		return previous[text.len()];
+	}
+
+	/// A random sequence over a small vocabulary, so that tokens repeat like words do
+	fn sequence(rng: &mut Rng, len: usize) -> Vec<u32> {
+		return (0..len).map(|_| rng.below(12) as u32).collect();
+	}
+
+	/// A copy with tokens inserted, deleted and replaced, like a transcript of the same
+	/// episode would be
+	fn edited(rng: &mut Rng, tokens: &[u32], edits: usize) -> Vec<u32> {
+		let mut copy = tokens.to_vec();
+		for _ in 0..edits {
+			let idx = rng.below(copy.len() + 1);
+			match rng.below(3) {
+				0 => copy.insert(idx, rng.below(12) as u32),
+				1 if idx < copy.len() => {
+					copy.remove(idx);
+				}
+				_ if idx < copy.len() => copy[idx] = rng.below(12) as u32,
+				_ => {}
+			}
+		}
+		return copy;
+	}
+
+	/// Checks every kind of bound for one pair: none, exactly the distance, one either
+	/// side of it, and a random one
+	fn check(rng: &mut Rng, pattern: &[u32], text: &[u32]) {
+		let expected = plain_distance(pattern, text);
+		let describe = || format!("pattern {pattern:?}\ntext {text:?}");
+		assert_eq!(
+			bounded_distance(pattern, text, usize::MAX),
+			Some(expected),
+			"unbounded\n{}",
+			describe()
+		);
+		assert_eq!(
+			bounded_distance(pattern, text, expected),
+			Some(expected),
+			"bound hit exactly\n{}",
+			describe()
+		);
+		assert_eq!(
+			bounded_distance(pattern, text, expected + 1),
+			Some(expected),
+			"bound one above\n{}",
+			describe()
+		);
+		if expected > 0 {
+			assert_eq!(
+				bounded_distance(pattern, text, expected - 1),
+				None,
+				"bound one below\n{}",
+				describe()
+			);
+		}
+		let bound = rng.below(expected * 2 + 2);
+		assert_eq!(
+			bounded_distance(pattern, text, bound),
+			(expected <= bound).then_some(expected),
+			"bound {bound}\n{}",
+			describe()
+		);
+	}
+
+	#[test]
+	fn empty_inputs() {
+		assert_eq!(bounded_distance(&[], &[], 0), Some(0));
+		assert_eq!(bounded_distance(&[], &[1, 2, 3], usize::MAX), Some(3));
+		assert_eq!(bounded_distance(&[1, 2, 3], &[], usize::MAX), Some(3));
+		assert_eq!(bounded_distance(&[1, 2, 3], &[], 3), Some(3));
+		assert_eq!(bounded_distance(&[1, 2, 3], &[], 2), None);
+	}
+
+	#[test]
+	fn unrelated_sequences_match_plain_distance() {
+		let mut rng = Rng(0x5eed);
+		for _ in 0..300 {
+			let (rows, columns) = (rng.below(200), rng.below(200));
+			let pattern = sequence(&mut rng, rows);
+			let text = sequence(&mut rng, columns);
+			check(&mut rng, &pattern, &text);
+		}
+	}
+
+	#[test]
+	fn similar_sequences_match_plain_distance() {
+		let mut rng = Rng(0xfeed);
+		for _ in 0..300 {
+			let rows = 1 + rng.below(300);
+			let pattern = sequence(&mut rng, rows);
+			let edits = rng.below(pattern.len() / 4 + 2);
+			let text = edited(&mut rng, &pattern, edits);
+			check(&mut rng, &pattern, &text);
+		}
+	}
+
+	#[test]
+	fn block_boundaries_match_plain_distance() {
+		// Lengths on either side of one, two and three 64-token blocks
+		let lengths = [1, 63, 64, 65, 127, 128, 129, 191, 192, 193];
+		let mut rng = Rng(0xb10c);
+		for rows in lengths {
+			for columns in lengths {
+				let pattern = sequence(&mut rng, rows);
+				let text = sequence(&mut rng, columns);
+				check(&mut rng, &pattern, &text);
+				// A near copy has a small distance, so the band is about as narrow as it
+				// gets
+				let text = edited(&mut rng, &pattern, 3);
+				check(&mut rng, &pattern, &text);
+			}
+		}
+	}
+
+	#[test]
+	fn length_difference_at_the_edge_of_the_band() {
+		let mut rng = Rng(0xed9e);
+		for extra in [1, 5, 64, 70, 130] {
+			let pattern = sequence(&mut rng, 100);
+			let mut text = pattern.clone();
+			text.extend(sequence(&mut rng, extra));
+			// Only insertions are needed, so the distance is the length difference
+			assert_eq!(bounded_distance(&pattern, &text, extra), Some(extra));
+			assert_eq!(bounded_distance(&pattern, &text, extra - 1), None);
+			assert_eq!(bounded_distance(&text, &pattern, extra), Some(extra));
+			assert_eq!(bounded_distance(&text, &pattern, extra - 1), None);
+		}
+	}
+}
@@ -1,11 +1,22 @@
 use crate::task_queue::TaskQueue;
-use crate::{get_st_track::get_comparison_track, interact::interact, THEME};
-use anyhow::anyhow;
-use std::path::PathBuf;
+use crate::{
+	alignment::{write_srt, Cue},
+	get_st_track::get_comparison_track,
+	language::language,
+	pgs::decode_sup,
+};
+use anyhow::{anyhow, Context};
+use std::path::{Path, PathBuf};
 use std::process::Stdio;
-use std::time::Duration;
 use tokio::{fs, process::Command};
 
+/// Characters Tesseract isn't allowed to read, as it sees them in the edges of letters
+/// more often than subtitles use them
+const CHAR_BLACKLIST: &str = "tessedit_char_blacklist=|\\/`_~!";
+
+/// Number of captions that are written out as images and read by Tesseract at a time
+const OCR_BATCH: usize = 200;
+
 pub async fn extract_subtitles(skip_ocr: bool, files: Option<Vec<PathBuf>>) -> anyhow::Result<()> {
 	let ocr_queue = if skip_ocr {
 		None
@@ -74,49 +85,15 @@ pub async fn extract_subtitles(skip_ocr: bool, files: Option<Vec<PathBuf>>) -> a
 				match st_track.codec_id.as_str() {
 					"S_VOBSUB" => vobsubocr = true,
 					"S_HDMV/PGS" => {
-						let bdsup_path = match std::env::var("BDSUP2SUB_PATH") {
-							Ok(path) => path,
-							Err(_) => interact(|| {
-								dialoguer::Input::with_theme(&*THEME)
-									.with_prompt("Path to BDSup2Sub.jar")
-									.interact_text()
-							})
-							.await
-							.unwrap(),
-						};
-						let bdsup_result = Command::new("java")
-							.args(["-jar", &bdsup_path, "-o"])
-							.arg(file.with_extension("sub"))
-							.arg(file.with_extension("sup"))
-							.stdin(Stdio::null())
-							.stdout(Stdio::null())
-							.spawn()
-							.unwrap()
-							.wait()
-							.await
-							.unwrap();
-
-						// This program seems to exit before it's actually finished. May need to do some bugfixing...
-						// if bdsup_result.success() {}
-						tokio::time::sleep(Duration::from_secs(1)).await;
-						let sup_file = file.with_extension("sup");
-						if let Err(err) = fs::remove_file(&sup_file).await {
-							println!(
-								"Could not delete {}. Error:\n{}",
-								sup_file
-									.file_name()
-									.and_then(|inner| inner.to_str())
-									.unwrap_or("unknown file"),
-								err
-							)
+						if let Err(err) = ocr_pgs(&file).await {
+							println!("Could not run OCR on {}. Error:\n{:?}", file.display(), err);
 						}
-						vobsubocr = true;
 					}
 					_ => {}
 				}
 				if vobsubocr {
 					let ocr_result = Command::new("vobsubocr")
-						.args(["-c", "tessedit_char_blacklist=|\\/`_~!", "-l", "eng", "-o"])
+						.args(["-c", CHAR_BLACKLIST, "-l", language().tesseract, "-o"])
 						.arg(file.with_extension("srt"))
 						.arg(file.with_extension("idx"))
 						.spawn()
@@ -160,3 +137,112 @@ pub async fn extract_subtitles(skip_ocr: bool, files: Option<Vec<PathBuf>>) -> a
 
 	return Ok(());
 }
+
+/// Reads the captions of a Blu-ray subtitle track with Tesseract, writing them to an
+/// srt file next to the title. The captions are decoded here, then handed to
+/// Tesseract in batches of images, one caption per page.
+async fn ocr_pgs(file: &Path) -> anyhow::Result<()> {
+	let sup_file = file.with_extension("sup");
+	let data = fs::read(&sup_file)
+		.await
+		.with_context(|| format!("Couldn't read {}", sup_file.display()))?;
+	let captions = tokio::task::spawn_blocking(move || decode_sup(&data))
+		.await?
+		.with_context(|| format!("{} is not a valid PGS stream", sup_file.display()))?;
+
+	let folder = std::env::temp_dir().join(format!(
+		"plex-autotagger-{}-{}",
+		std::process::id(),
+		file.file_stem().unwrap_or_default().to_string_lossy()
+	));
+	fs::create_dir_all(&folder)
+		.await
+		.with_context(|| format!("Couldn't create {}", folder.display()))?;
+	let mut cues = Vec::<Cue>::new();
+	let result = async {
+		// Each batch writes over the images of the one before
+		for batch in captions.chunks(OCR_BATCH) {
+			let mut list = String::new();
+			for (idx, caption) in batch.iter().enumerate() {
+				let image = folder.join(format!("{idx:03}.pgm"));
+				fs::write(&image, caption.image.to_pgm()).await?;
+				list.push_str(&format!("{}\n", image.display()));
+			}
+			let list_file = folder.join("images.txt");
+			fs::write(&list_file, list).await?;
+			// Page segmentation mode 6 reads the image as a single block of text
+			let output = Command::new("tesseract")
+				.arg(&list_file)
+				.arg("stdout")
+				.args([
+					"-l",
+					language().tesseract,
+					"--psm",
+					"6",
+					"-c",
+					CHAR_BLACKLIST,
+				])
+				.stdin(Stdio::null())
+				.stderr(Stdio::null())
+				.output()
+				.await
+				.context("Couldn't run tesseract")?;
+			if !output.status.success() {
+				return Err(anyhow!("tesseract failed with {}", output.status));
+			}
+			// Pages are separated by form feeds
+			let text = String::from_utf8_lossy(&output.stdout);
+			let pages: Vec<&str> = text.split('\u{c}').collect();
+			if pages.len() < batch.len() {
+				return Err(anyhow!(
+					"tesseract read {} pages out of {} images",
+					pages.len(),
+					batch.len()
+				));
+			}
+			for (caption, page) in batch.iter().zip(pages) {
+				let text = page
+					.lines()
+					.map(str::trim)
+					.filter(|line| !line.is_empty())
+					.collect::<Vec<&str>>()
+					.join("\n");
+				if text.is_empty() {
+					continue;
+				}
+				// A caption that fades in or out can come out as the same text several
+				// times over
+				match cues.last_mut() {
+					Some(last) if last.text == text && last.end == caption.start => {
+						last.end = caption.end;
+					}
+					_ => cues.push(Cue {
+						start: caption.start,
+						end: caption.end,
+						text,
+					}),
+				}
+			}
+		}
+		return anyhow::Ok(());
+	}
+	.await;
+	let _ = fs::remove_dir_all(&folder).await;
+	result?;
+
+	let srt_file = file.with_extension("srt");
+	fs::write(&srt_file, write_srt(&cues))
+		.await
+		.with_context(|| format!("Couldn't write {}", srt_file.display()))?;
+	if let Err(err) = fs::remove_file(&sup_file).await {
+		println!(
+			"Could not delete {}. Error:\n{}",
+			sup_file
+				.file_name()
+				.and_then(|inner| inner.to_str())
+				.unwrap_or("unknown file"),
+			err
+		);
+	}
+	return Ok(());
+}
@@ -0,0 +1,138 @@
+use std::{
+	path::{Path, PathBuf},
+	time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{autotagger::UNASSIGNED_COST, config::config, runtime::plausible_runtime};
+
+/// Folder that titles are moved into when they're not worth keeping in the library,
+/// unless `--quarantine` or the config file say otherwise
+const DEFAULT_QUARANTINE: &str = "Quarantine";
+
+/// Titles shorter than this are trailers, promos, menus and the like
+const SHORT_EXTRA: Duration = Duration::from_secs(5 * 60);
+
+/// Titles at least this many times longer than an episode contain several of them
+pub const PLAY_ALL_RATIO: f64 = 1.8;
+
+/// Words per minute above which an extra is mostly people talking to the camera, as in
+/// interviews and commentary. Scripted scenes are usually well below this.
+const INTERVIEW_WORDS_PER_MINUTE: f64 = 130.0;
+
+/// What a title that didn't match anything most likely is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExtraKind {
+	Featurette,
+	BehindTheScenes,
+	Other,
+	/// Several episodes played back to back
+	PlayAll,
+	/// Another copy of something that was already matched
+	Duplicate,
+	/// As long as an episode, but unlike any of them. This is most likely an episode
+	/// that wasn't selected or has bad subtitles, so it's kept out of the extras.
+	Unmatched,
+}
+impl ExtraKind {
+	/// Folder Plex looks for this kind of extra in, or `None` if it shouldn't go in the
+	/// library at all
+	pub fn folder(&self) -> Option<&'static str> {
+		return match self {
+			ExtraKind::Featurette => Some("Featurettes"),
+			ExtraKind::BehindTheScenes => Some("Behind The Scenes"),
+			ExtraKind::Other => Some("Other"),
+			ExtraKind::PlayAll | ExtraKind::Duplicate | ExtraKind::Unmatched => None,
+		};
+	}
+
+	pub fn describe(&self) -> &'static str {
+		return match self {
+			ExtraKind::Featurette => "featurette",
+			ExtraKind::BehindTheScenes => "behind the scenes",
+			ExtraKind::Other => "other extra",
+			ExtraKind::PlayAll => "play all",
+			ExtraKind::Duplicate => "duplicate",
+			ExtraKind::Unmatched => "unmatched, full length",
+		};
+	}
+}
+
+/// What's known about a title that didn't match anything
+pub struct TitleProfile {
+	pub duration: Option<Duration>,
+	/// Length of its subtitles in words
+	pub words: usize,
+	/// Whether it matched something well, but something else matched it better
+	pub duplicate: bool,
+}
+
+/// Guesses what a title is from its runtime and how much dialogue it has, given the
+/// runtime of a typical episode (or the movie)
+pub fn classify(title: &TitleProfile, typical_runtime: Option<Duration>) -> ExtraKind {
+	if title.duplicate {
+		return ExtraKind::Duplicate;
+	}
+	let Some(duration) = title.duration else {
+		return ExtraKind::Other;
+	};
+	if let Some(typical_runtime) = typical_runtime {
+		if duration.as_secs_f64() >= typical_runtime.as_secs_f64() * PLAY_ALL_RATIO {
+			return ExtraKind::PlayAll;
+		}
+		if plausible_runtime(Some(duration), Some(typical_runtime)) {
+			return ExtraKind::Unmatched;
+		}
+	}
+	if duration < SHORT_EXTRA || title.words == 0 {
+		return ExtraKind::Other;
+	}
+	let words_per_minute = title.words as f64 / (duration.as_secs_f64() / 60.0);
+	if words_per_minute >= INTERVIEW_WORDS_PER_MINUTE {
+		return ExtraKind::BehindTheScenes;
+	}
+	return ExtraKind::Featurette;
+}
+
+/// Where an extra should be moved to. Extras worth keeping go in the matching folder
+/// inside of `root`, and the rest go to the quarantine folder.
+pub fn extra_destination(
+	kind: ExtraKind,
+	root: &Path,
+	quarantine: Option<&Path>,
+	source: &Path,
+) -> PathBuf {
+	let file_name = source.file_name().unwrap_or(source.as_os_str());
+	return match kind.folder() {
+		Some(folder) => root.join(folder).join(file_name),
+		None => quarantine
+			.map(Path::to_owned)
+			.or_else(|| config().quarantine.clone())
+			.unwrap_or_else(|| PathBuf::from(DEFAULT_QUARANTINE))
+			.join(file_name),
+	};
+}
+
+/// How sure we are that a title is an extra, from 0 to 1. That's how far it fell short
+/// of matching anything, or for duplicates, how well it matched the original.
+pub fn extra_confidence(similarity: f64, duplicate: bool) -> f64 {
+	let threshold = 1.0 - UNASSIGNED_COST;
+	if duplicate {
+		if similarity <= 0.0 {
+			return 0.0;
+		}
+		return ((similarity - threshold) / similarity).clamp(0.0, 1.0);
+	}
+	return ((threshold - similarity) / threshold).clamp(0.0, 1.0);
+}
+
+/// Middle value of a set of runtimes, which isn't thrown off by the odd double episode
+pub fn typical_runtime(mut durations: Vec<Duration>) -> Option<Duration> {
+	if durations.is_empty() {
+		return None;
+	}
+	durations.sort_unstable();
+	return Some(durations[durations.len() / 2]);
+}
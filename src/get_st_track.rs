@@ -1,21 +1,31 @@
-use anyhow::Context;
+use anyhow::{anyhow, Context};
 use dialoguer::Select;
 use matroska::{Track, Tracktype};
 use std::path::Path;
 
-use crate::{interact::interact, THEME};
+use crate::{
+	interact::{interact, is_unattended},
+	language::language,
+	THEME,
+};
 
 /// Gets the track to be used for comparison with OST, attempting to automatically
 /// deduce the best one or by prompting the user.
 pub async fn get_comparison_track(file: &Path) -> anyhow::Result<Option<Track>> {
 	let mut tracks = get_subtitle_tracks(file)?;
-	if tracks.len() == 0 {
+	if tracks.is_empty() {
 		return Ok(None);
 	}
 	let default_track = get_default_track(&tracks).cloned();
 	let selected_track = match default_track {
 		Some(track) => track,
 		None => {
+			if is_unattended() {
+				return Err(anyhow!(
+					"{} has several subtitle tracks and none of them is the default",
+					file.display()
+				));
+			}
 			let track_option_strings = tracks
 				.iter()
 				.map(|track| {
@@ -43,7 +53,7 @@ pub async fn get_comparison_track(file: &Path) -> anyhow::Result<Option<Track>>
 /// Tries to narrow down which subtitles track is preferrable.
 /// If we are able to narrow it down to exactly one, it is returned.
 /// Otherwise, this function returns None.
-fn get_default_track<'a>(tracks: &'a Vec<Track>) -> Option<&'a Track> {
+fn get_default_track(tracks: &[Track]) -> Option<&Track> {
 	return match tracks.len() {
 		0 => None,
 		1 => Some(&tracks[0]),
@@ -58,7 +68,7 @@ fn get_default_track<'a>(tracks: &'a Vec<Track>) -> Option<&'a Track> {
 	};
 }
 
-/// Gets a list of subtitle tracks from an MKV file
+/// Gets a list of subtitle tracks in the chosen language from an MKV file
 fn get_subtitle_tracks(file: &Path) -> anyhow::Result<Vec<Track>> {
 	let vid = matroska::open(file).context("Couldn't open video file")?;
 	let tracks: Vec<Track> = vid
@@ -69,15 +79,21 @@ fn get_subtitle_tracks(file: &Path) -> anyhow::Result<Vec<Track>> {
 			track
 				.language
 				.as_ref()
-				.map(|lang| match lang {
-					matroska::Language::ISO639(lang) if lang == "eng" => true,
-					matroska::Language::IETF(lang) if lang == "en" => true,
-					matroska::Language::IETF(lang) if lang == "en-US" => true,
-					matroska::Language::IETF(lang) if lang == "en-GB" => true,
-					_ => false,
-				})
-				.unwrap_or(false)
+				.is_some_and(|lang| language().matches_track(lang))
 		})
 		.collect();
 	return Ok(tracks);
 }
+
+/// Whether the subtitles that would be compared are images, which
+/// `extract_subtitles` runs through OCR. Files that can't be read count as text.
+pub fn has_image_subtitles(file: &Path) -> bool {
+	let Ok(tracks) = get_subtitle_tracks(file) else {
+		return false;
+	};
+	let is_image = |track: &Track| matches!(track.codec_id.as_str(), "S_VOBSUB" | "S_HDMV/PGS");
+	return match get_default_track(&tracks) {
+		Some(track) => is_image(track),
+		None => !tracks.is_empty() && tracks.iter().all(is_image),
+	};
+}
@@ -1,5 +1,9 @@
-use std::future::Future;
+use std::{
+	future::Future,
+	sync::atomic::{AtomicBool, Ordering},
+};
 
+use anyhow::anyhow;
 use lazy_static::lazy_static;
 use tokio::sync::oneshot;
 
@@ -10,13 +14,37 @@ lazy_static! {
 	static ref QUEUE: TaskQueue = TaskQueue::new();
 }
 
+/// Set when the user asked us not to prompt for anything
+static UNATTENDED: AtomicBool = AtomicBool::new(false);
+
+/// Disables prompts for the rest of the process. Anything that would have needed an
+/// answer from the user must be provided some other way, or it becomes an error.
+pub fn set_unattended(unattended: bool) {
+	UNATTENDED.store(unattended, Ordering::Relaxed);
+}
+
+pub fn is_unattended() -> bool {
+	return UNATTENDED.load(Ordering::Relaxed);
+}
+
+/// Fails with an explanation of what was missing if prompts have been disabled.
+/// Call this before prompting for something the user could have provided up front.
+pub fn ensure_interactive(missing: &str) -> anyhow::Result<()> {
+	if is_unattended() {
+		return Err(anyhow!(
+			"{missing} must be provided when running unattended"
+		));
+	}
+	return Ok(());
+}
+
 /// This function allows concurrent async threads to share the console.
 pub async fn interact<T: Send + Sync + 'static>(
 	func: impl FnOnce() -> T + Send + Sync + 'static,
 ) -> T {
 	let (sender, receiver) = oneshot::channel();
 	QUEUE.add_task(async move {
-		let _ = sender.send(tokio::task::spawn_blocking(|| func()).await.unwrap());
+		let _ = sender.send(tokio::task::spawn_blocking(func).await.unwrap());
 	});
 
 	return receiver.await.unwrap();
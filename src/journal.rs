@@ -0,0 +1,346 @@
+use std::{
+	collections::BTreeMap,
+	path::{Path, PathBuf},
+	time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, Context};
+use clap::Args;
+use dialoguer::Confirm;
+use serde::{Deserialize, Serialize};
+use tokio::{fs, io::AsyncWriteExt};
+
+use crate::{interact::interact, plan::move_file, THEME};
+
+/// Append-only record of every change made to the working directory
+const JOURNAL_FILE: &str = ".autotagger-journal.jsonl";
+
+/// Deleted files are kept in here, in a folder per session, until they're pruned
+const TRASH_DIR: &str = ".autotagger-trash";
+
+/// A single line of the journal
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JournalEntry {
+	/// Every run of the program gets its own session, named after when it started
+	pub session: u64,
+	/// Seconds since the unix epoch
+	pub timestamp: u64,
+	#[serde(flatten)]
+	pub action: Action,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum Action {
+	/// A file was renamed, and its sidecars moved to the trash
+	Rename {
+		source: PathBuf,
+		target: PathBuf,
+		#[serde(default)]
+		trashed: Vec<TrashedFile>,
+		/// A file that was already at `target`, and was moved to the trash to make room
+		#[serde(default)]
+		replaced: Option<TrashedFile>,
+		/// Folders that were created to hold `target`, deepest first
+		#[serde(default)]
+		created: Vec<PathBuf>,
+	},
+	/// Files were moved to the trash without replacing anything
+	Delete { trashed: Vec<TrashedFile> },
+	/// A play-all title was cut into new files, which undo removes again
+	Split {
+		source: PathBuf,
+		created: Vec<PathBuf>,
+	},
+	/// Everything done in an earlier session was reversed
+	Undo { undone: u64 },
+}
+
+/// Where a deleted file used to be, and where it's kept now
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrashedFile {
+	pub original: PathBuf,
+	pub trash: PathBuf,
+}
+
+/// Records changes made during this run so they can be reversed with `undo`
+pub struct Journal {
+	session: u64,
+}
+
+impl Journal {
+	pub fn start() -> Self {
+		return Self {
+			session: unix_time().as_millis() as u64,
+		};
+	}
+
+	/// Moves a file into this session's trash folder instead of deleting it
+	pub async fn trash(&self, path: &Path) -> anyhow::Result<TrashedFile> {
+		let folder = Path::new(TRASH_DIR).join(self.session.to_string());
+		fs::create_dir_all(&folder)
+			.await
+			.with_context(|| format!("Couldn't create {}", folder.display()))?;
+		let file_name = path
+			.file_name()
+			.ok_or_else(|| anyhow!("{} is not a file", path.display()))?;
+		// Two files with the same name, such as episodes replaced in different
+		// libraries, mustn't end up on top of each other
+		let mut trash = folder.join(file_name);
+		let mut copy = 1;
+		while fs::try_exists(&trash).await? {
+			copy += 1;
+			let mut name = file_name.to_owned();
+			name.push(format!(".{copy}"));
+			trash = folder.join(name);
+		}
+		move_file(path, &trash)
+			.await
+			.with_context(|| format!("Failed to remove {}", path.display()))?;
+		return Ok(TrashedFile {
+			original: path.to_owned(),
+			trash,
+		});
+	}
+
+	pub async fn record(&self, action: Action) -> anyhow::Result<()> {
+		let entry = JournalEntry {
+			session: self.session,
+			timestamp: unix_time().as_secs(),
+			action,
+		};
+		let mut line = serde_json::to_string(&entry)?;
+		line.push('\n');
+		let mut file = fs::OpenOptions::new()
+			.create(true)
+			.append(true)
+			.open(JOURNAL_FILE)
+			.await
+			.with_context(|| format!("Couldn't open {JOURNAL_FILE}"))?;
+		file.write_all(line.as_bytes()).await?;
+		file.flush().await?;
+		return Ok(());
+	}
+}
+
+async fn read_journal() -> anyhow::Result<Vec<JournalEntry>> {
+	let contents = match fs::read_to_string(JOURNAL_FILE).await {
+		Ok(contents) => contents,
+		Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+		Err(err) => return Err(err).with_context(|| format!("Couldn't read {JOURNAL_FILE}")),
+	};
+	return contents
+		.lines()
+		.filter(|line| !line.trim().is_empty())
+		.enumerate()
+		.map(|(idx, line)| {
+			serde_json::from_str(line)
+				.with_context(|| format!("Line {} of {JOURNAL_FILE} is corrupt", idx + 1))
+		})
+		.collect();
+}
+
+#[derive(Args)]
+pub struct UndoArgs {
+	/// Session to reverse, as shown by --list. Defaults to the most recent one.
+	#[arg(long)]
+	session: Option<u64>,
+
+	/// Lists the sessions in the journal instead of undoing anything
+	#[arg(long, conflicts_with_all = ["session", "prune"])]
+	list: bool,
+
+	/// Forgets every session in the journal and empties the trash
+	#[arg(long, conflicts_with = "session")]
+	prune: bool,
+}
+
+/// Summary of one session, built from the journal
+#[derive(Default)]
+struct Session {
+	timestamp: u64,
+	/// Renames and deletions, in the order they were made
+	changes: Vec<JournalEntry>,
+	undone: bool,
+}
+
+pub async fn undo(args: UndoArgs) -> anyhow::Result<()> {
+	let entries = read_journal().await?;
+	let mut sessions = BTreeMap::<u64, Session>::new();
+	for entry in &entries {
+		match entry.action {
+			Action::Rename { .. } | Action::Delete { .. } | Action::Split { .. } => {
+				let session = sessions.entry(entry.session).or_default();
+				session.timestamp = entry.timestamp;
+				session.changes.push(entry.clone());
+			}
+			Action::Undo { undone } => {
+				sessions.entry(undone).or_default().undone = true;
+			}
+		}
+	}
+
+	if args.list {
+		if sessions.is_empty() {
+			println!("The journal is empty");
+		}
+		let now = unix_time().as_secs();
+		for (id, session) in &sessions {
+			let count = |matches: fn(&Action) -> bool| {
+				session
+					.changes
+					.iter()
+					.filter(|entry| matches(&entry.action))
+					.count()
+			};
+			let deleted = count(|action| matches!(action, Action::Delete { .. }));
+			let split = count(|action| matches!(action, Action::Split { .. }));
+			println!(
+				"{id}: {} file(s) renamed{}{} {}{}",
+				session.changes.len() - deleted - split,
+				match deleted {
+					0 => String::new(),
+					_ => format!(", {deleted} deleted"),
+				},
+				match split {
+					0 => String::new(),
+					_ => format!(", {split} split"),
+				},
+				describe_age(now.saturating_sub(session.timestamp)),
+				if session.undone { " (undone)" } else { "" },
+			);
+		}
+		return Ok(());
+	}
+
+	if args.prune {
+		let prompt = format!(
+			"Forget {} session(s) and permanently delete the trash?",
+			sessions.len()
+		);
+		if !interact(|| Confirm::with_theme(&*THEME).with_prompt(prompt).interact()).await? {
+			return Ok(());
+		}
+		if fs::try_exists(TRASH_DIR).await? {
+			fs::remove_dir_all(TRASH_DIR).await?;
+		}
+		if fs::try_exists(JOURNAL_FILE).await? {
+			fs::remove_file(JOURNAL_FILE).await?;
+		}
+		return Ok(());
+	}
+
+	let (id, session) = match args.session {
+		Some(id) => (
+			id,
+			sessions
+				.get(&id)
+				.ok_or_else(|| anyhow!("There is no session {id} in the journal"))?,
+		),
+		None => sessions
+			.iter()
+			.rev()
+			.find(|(_, session)| !session.undone)
+			.map(|(id, session)| (*id, session))
+			.ok_or_else(|| anyhow!("There is nothing to undo"))?,
+	};
+	if session.undone {
+		return Err(anyhow!("Session {id} has already been undone"));
+	}
+
+	// Check everything up front so we don't stop halfway through
+	for entry in &session.changes {
+		match entry.action {
+			Action::Rename {
+				ref source,
+				ref target,
+				ref trashed,
+				..
+			} => {
+				if !fs::try_exists(target).await? {
+					return Err(anyhow!(
+						"{} has been moved since it was renamed",
+						target.display()
+					));
+				}
+				for path in trashed.iter().map(|file| &file.original).chain([source]) {
+					if fs::try_exists(path).await? {
+						return Err(anyhow!("Refusing to overwrite {}", path.display()));
+					}
+				}
+			}
+			Action::Delete { ref trashed } => {
+				for file in trashed {
+					if fs::try_exists(&file.original).await? {
+						return Err(anyhow!("Refusing to overwrite {}", file.original.display()));
+					}
+				}
+			}
+			Action::Split { .. } | Action::Undo { .. } => {}
+		}
+	}
+
+	let journal = Journal::start();
+	for entry in session.changes.iter().rev() {
+		if let Action::Delete { ref trashed } = entry.action {
+			for file in trashed {
+				println!("Restoring {:?}", &file.original);
+				move_file(&file.trash, &file.original)
+					.await
+					.with_context(|| format!("Couldn't restore {}", file.original.display()))?;
+			}
+			continue;
+		}
+		if let Action::Split { ref created, .. } = entry.action {
+			// The parts were renamed back above, and the title they came from was never
+			// touched
+			for part in created {
+				if fs::try_exists(part).await? {
+					println!("Removing {:?}", part);
+					fs::remove_file(part)
+						.await
+						.with_context(|| format!("Couldn't remove {}", part.display()))?;
+				}
+			}
+			continue;
+		}
+		let Action::Rename {
+			ref source,
+			ref target,
+			ref trashed,
+			ref replaced,
+			ref created,
+		} = entry.action
+		else {
+			continue;
+		};
+		println!("{:?} => {:?}", target, source);
+		move_file(target, source).await?;
+		for file in trashed.iter().chain(replaced) {
+			move_file(&file.trash, &file.original)
+				.await
+				.with_context(|| format!("Couldn't restore {}", file.original.display()))?;
+		}
+		// Folders that still have something in them are left alone
+		for folder in created {
+			let _ = fs::remove_dir(folder).await;
+		}
+	}
+	// Only removes the folder if everything in it was restored
+	let _ = fs::remove_dir(Path::new(TRASH_DIR).join(id.to_string())).await;
+	journal.record(Action::Undo { undone: id }).await?;
+	return Ok(());
+}
+
+pub fn unix_time() -> Duration {
+	return SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+}
+
+fn describe_age(seconds: u64) -> String {
+	return match seconds {
+		0..=59 => String::from("just now"),
+		60..=3599 => format!("{} minute(s) ago", seconds / 60),
+		3600..=86399 => format!("{} hour(s) ago", seconds / 3600),
+		_ => format!("{} day(s) ago", seconds / 86400),
+	};
+}
@@ -0,0 +1,203 @@
+use std::sync::OnceLock;
+
+use anyhow::anyhow;
+
+use crate::config::config;
+
+/// A language that subtitles can be compared in, with the codes each of the tools
+/// involved knows it by
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Language {
+	/// ISO 639-1 code, followed by the region for variants such as "pt-br"
+	pub code: &'static str,
+	/// Code that OpenSubtitles knows the language by, which names the region for some
+	pub opensubtitles: &'static str,
+	/// ISO 639-2 codes, as used by mkv files. Some languages have two.
+	pub iso639_2: &'static [&'static str],
+	/// Name of Tesseract's trained data for the language
+	pub tesseract: &'static str,
+}
+
+const LANGUAGES: &[Language] = &[
+	Language {
+		code: "en",
+		opensubtitles: "en",
+		iso639_2: &["eng"],
+		tesseract: "eng",
+	},
+	Language {
+		code: "de",
+		opensubtitles: "de",
+		iso639_2: &["ger", "deu"],
+		tesseract: "deu",
+	},
+	Language {
+		code: "ja",
+		opensubtitles: "ja",
+		iso639_2: &["jpn"],
+		tesseract: "jpn",
+	},
+	Language {
+		code: "fr",
+		opensubtitles: "fr",
+		iso639_2: &["fre", "fra"],
+		tesseract: "fra",
+	},
+	Language {
+		code: "es",
+		opensubtitles: "es",
+		iso639_2: &["spa"],
+		tesseract: "spa",
+	},
+	Language {
+		code: "it",
+		opensubtitles: "it",
+		iso639_2: &["ita"],
+		tesseract: "ita",
+	},
+	Language {
+		code: "nl",
+		opensubtitles: "nl",
+		iso639_2: &["dut", "nld"],
+		tesseract: "nld",
+	},
+	Language {
+		code: "pt",
+		opensubtitles: "pt-PT",
+		iso639_2: &["por"],
+		tesseract: "por",
+	},
+	Language {
+		code: "pt-br",
+		opensubtitles: "pt-BR",
+		iso639_2: &["por"],
+		tesseract: "por",
+	},
+	Language {
+		code: "sv",
+		opensubtitles: "sv",
+		iso639_2: &["swe"],
+		tesseract: "swe",
+	},
+	Language {
+		code: "da",
+		opensubtitles: "da",
+		iso639_2: &["dan"],
+		tesseract: "dan",
+	},
+	Language {
+		code: "no",
+		opensubtitles: "no",
+		iso639_2: &["nor", "nob"],
+		tesseract: "nor",
+	},
+	Language {
+		code: "fi",
+		opensubtitles: "fi",
+		iso639_2: &["fin"],
+		tesseract: "fin",
+	},
+	Language {
+		code: "pl",
+		opensubtitles: "pl",
+		iso639_2: &["pol"],
+		tesseract: "pol",
+	},
+	Language {
+		code: "cs",
+		opensubtitles: "cs",
+		iso639_2: &["cze", "ces"],
+		tesseract: "ces",
+	},
+	Language {
+		code: "hu",
+		opensubtitles: "hu",
+		iso639_2: &["hun"],
+		tesseract: "hun",
+	},
+	Language {
+		code: "tr",
+		opensubtitles: "tr",
+		iso639_2: &["tur"],
+		tesseract: "tur",
+	},
+	Language {
+		code: "el",
+		opensubtitles: "el",
+		iso639_2: &["gre", "ell"],
+		tesseract: "ell",
+	},
+	Language {
+		code: "ru",
+		opensubtitles: "ru",
+		iso639_2: &["rus"],
+		tesseract: "rus",
+	},
+	Language {
+		code: "ko",
+		opensubtitles: "ko",
+		iso639_2: &["kor"],
+		tesseract: "kor",
+	},
+	Language {
+		code: "zh",
+		opensubtitles: "zh-CN",
+		iso639_2: &["chi", "zho"],
+		tesseract: "chi_sim",
+	},
+	Language {
+		code: "zh-tw",
+		opensubtitles: "zh-TW",
+		iso639_2: &["chi", "zho"],
+		tesseract: "chi_tra",
+	},
+];
+
+impl Language {
+	/// Accepts ISO 639-1 and 639-2 codes, as well as language tags such as "en-US".
+	/// Tags of a variant with subtitles of its own, such as "pt-BR", pick the variant.
+	pub fn parse(value: &str) -> Result<Self, String> {
+		let tag = value.replace('_', "-").to_lowercase();
+		let primary = tag.split('-').next().unwrap_or_default();
+		return LANGUAGES
+			.iter()
+			.find(|language| language.code == tag)
+			.or_else(|| {
+				LANGUAGES.iter().find(|language| {
+					language.code == primary || language.iso639_2.contains(&primary)
+				})
+			})
+			.copied()
+			.ok_or_else(|| format!("{value:?} is not a supported language"));
+	}
+
+	/// Whether an mkv track is tagged with this language
+	pub fn matches_track(&self, language: &matroska::Language) -> bool {
+		let code = match language {
+			matroska::Language::ISO639(code) => code,
+			matroska::Language::IETF(tag) => tag,
+		};
+		// Tracks are seldom tagged with a region, so variants match each other
+		return Language::parse(code).is_ok_and(|language| language.iso639_2 == self.iso639_2);
+	}
+}
+
+static LANGUAGE: OnceLock<Language> = OnceLock::new();
+
+/// Sets the language of the subtitles for the rest of the process: the one given on
+/// the command line, or else the `language` setting in the config file, or English
+pub fn set_language(language: Option<Language>) -> anyhow::Result<()> {
+	let language = match (language, config().language.as_deref()) {
+		(Some(language), _) => language,
+		(None, Some(language)) => {
+			Language::parse(language).map_err(|err| anyhow!("Invalid language in config: {err}"))?
+		}
+		(None, None) => LANGUAGES[0],
+	};
+	let _ = LANGUAGE.set(language);
+	return Ok(());
+}
+
+pub fn language() -> &'static Language {
+	return LANGUAGE.get_or_init(|| LANGUAGES[0]);
+}
@@ -1,15 +1,44 @@
+// Explicit returns are used throughout this crate for readability.
+#![allow(clippy::needless_return)]
+
+mod alignment;
+mod assignment;
+mod box_set;
+mod config;
+mod disc_label;
+mod duplicates;
+mod edit_distance;
 mod extract_subtitles;
+mod extras;
 mod get_st_track;
 mod interact;
+mod journal;
+mod language;
+mod movie;
+mod naming;
+mod normalize;
+mod ocr_correction;
 mod task_queue;
 mod autotagger;
 mod opensubtitles;
+mod pgs;
+mod plan;
+mod play_all;
+mod runtime;
 mod global_vars;
+mod similarity;
+#[cfg(test)]
+mod xorshift;
 
-use autotagger::tag_items;
+use autotagger::{tag_items, TagArgs};
 use clap::{Parser, Subcommand};
+use config::load_config;
 use extract_subtitles::extract_subtitles;
+use journal::{undo, UndoArgs};
+use language::{set_language, Language};
 use lazy_static::lazy_static;
+use movie::{tag_movie, TagMovieArgs};
+use plan::apply_plan;
 use std::path::PathBuf;
 
 lazy_static! {
@@ -26,32 +55,66 @@ struct Cli {
 enum AutotaggerCommand {
 	/// Extracts subtitles from a set of mkv files, generating srt files.
 	ExtractSubtitles {
-		/// Skips running OCR on bitmap-style subtitles, leaving them in sub/idx format
+		/// Skips running OCR on bitmap-style subtitles, leaving DVD tracks in sub/idx format
+		/// and Blu-ray tracks as .sup files
 		#[arg(short, long)]
 		skip_ocr: bool,
 
+		/// Language of the subtitle tracks to extract, such as "de". Defaults to the
+		/// `language` setting in the config file, or English.
+		#[arg(long, value_parser = Language::parse)]
+		language: Option<Language>,
+
 		#[arg()]
 		files: Vec<PathBuf>,
 	},
 
 	/// Scans subtitle files to identify requested episodes by way of subtitle comparison
-	Tag,
+	Tag(TagArgs),
+
+	/// Identifies the main feature of a movie, and any alternate cuts, by way of
+	/// subtitle comparison
+	TagMovie(TagMovieArgs),
+
+	/// Carries out a plan written by `tag --plan`
+	Apply {
+		#[arg()]
+		plan: PathBuf,
+	},
+
+	/// Reverses the renames made by an earlier run, restoring any deleted subtitles
+	Undo(UndoArgs),
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
 	let args = Cli::parse();
+	load_config()?;
 
 	match args.command {
-		AutotaggerCommand::ExtractSubtitles { skip_ocr, files } => {
-			if files.len() == 0 {
+		AutotaggerCommand::ExtractSubtitles {
+			skip_ocr,
+			language,
+			files,
+		} => {
+			set_language(language)?;
+			if files.is_empty() {
 				extract_subtitles(skip_ocr, None).await?;
 			} else {
 				extract_subtitles(skip_ocr, Some(files)).await?;
 			}
 		}
-		AutotaggerCommand::Tag => {
-			tag_items().await?;
+		AutotaggerCommand::Tag(args) => {
+			tag_items(args).await?;
+		}
+		AutotaggerCommand::TagMovie(args) => {
+			tag_movie(args).await?;
+		}
+		AutotaggerCommand::Apply { plan } => {
+			apply_plan(&plan).await?;
+		}
+		AutotaggerCommand::Undo(args) => {
+			undo(args).await?;
 		}
 	}
 
@@ -0,0 +1,424 @@
+use std::{path::PathBuf, time::Duration};
+
+use anyhow::{anyhow, Context};
+use clap::Args;
+use dialoguer::{Confirm, Select};
+use rayon::prelude::*;
+use tmdb_async::Movie;
+use urlencoding::encode;
+
+use crate::{
+	autotagger::{
+		describe_runtime, describe_sdh, describe_timing, get_duration, get_ripped_subtitles,
+		parse_confidence, sidecars, Match, Transcript, UNASSIGNED_COST,
+	},
+	config::config,
+	extras::{classify, extra_confidence, extra_destination, ExtraKind, TitleProfile},
+	global_vars::TMDB_API_KEY,
+	interact::{ensure_interactive, interact, is_unattended, set_unattended},
+	language::{set_language, Language},
+	naming::sanitize,
+	normalize::{set_sdh_annotations, SdhAnnotations},
+	ocr_correction::{correct_ripped, OcrCorrection},
+	opensubtitles::get_subtitles,
+	plan::{Plan, PlanEntry},
+	THEME,
+};
+
+#[derive(Args)]
+pub struct TagMovieArgs {
+	/// TMDB id of the movie, skipping the search
+	#[arg(long)]
+	tmdb_id: Option<u32>,
+
+	/// Renames matches at or above this confidence (0 to 1) without asking
+	#[arg(long, value_name = "CONFIDENCE", value_parser = parse_confidence)]
+	auto_accept: Option<f64>,
+
+	/// Language of the subtitles to compare, such as "de" or "ja". Defaults to the
+	/// `language` setting in the config file, or English.
+	#[arg(long, value_parser = Language::parse)]
+	language: Option<Language>,
+
+	/// What to do with annotations for the deaf and hard of hearing, such as
+	/// "[DOOR SLAMS]" or "JOHN:", in either set of subtitles. Defaults to the `sdh`
+	/// setting in the config file, or strip.
+	#[arg(long, value_enum, value_name = "ACTION")]
+	sdh: Option<SdhAnnotations>,
+
+	/// What to do about OCR errors, such as "rn" read instead of "m", in subtitles that
+	/// were extracted as images. They're corrected with the words of the reference
+	/// subtitles. Defaults to the `ocr_correction` setting in the config file, or apply.
+	#[arg(long, value_enum, value_name = "ACTION")]
+	ocr_correction: Option<OcrCorrection>,
+
+	/// Runs without prompting. Confirmations are answered with yes, except that
+	/// matches below --auto-accept are skipped, and any other missing input is an
+	/// error.
+	#[arg(short, long)]
+	yes: bool,
+
+	/// Writes the proposed changes to this file instead of renaming anything.
+	/// The plan can be reviewed or edited, then carried out with `apply`.
+	#[arg(long, value_name = "FILE")]
+	plan: Option<PathBuf>,
+
+	/// Moves the movie into its own folder inside of this library folder
+	#[arg(long, value_name = "DIR")]
+	library: Option<PathBuf>,
+
+	/// Replaces a movie that's already there. Replaced files are kept in the trash
+	/// until the journal is pruned.
+	#[arg(long)]
+	replace: bool,
+
+	/// Where duplicates, play-all titles and unmatched titles as long as the movie are
+	/// moved. Defaults to the `quarantine` setting in the config file, or "Quarantine".
+	#[arg(long, value_name = "DIR")]
+	quarantine: Option<PathBuf>,
+
+	/// Leaves titles that aren't a cut of the movie where they are, instead of sorting
+	/// them into extras folders or the quarantine folder
+	#[arg(long)]
+	leave_unmatched: bool,
+}
+
+/// Titles whose runtimes are this close together are treated as the same cut, such as
+/// alternate angles, or the same feature with different audio.
+const ANGLE_TOLERANCE: Duration = Duration::from_secs(1);
+
+/// How much longer or shorter a title has to be to count as a different cut
+const EDITION_MIN_DIFFERENCE: Duration = Duration::from_secs(120);
+
+/// How much more or less dialogue a title needs for its extra runtime to count as a
+/// different cut, rather than longer credits or studio logos
+const EDITION_MIN_DIALOGUE: f64 = 0.02;
+
+/// A title on the disc that matched the movie's subtitles
+struct Cut {
+	file_idx: usize,
+	duration: Option<Duration>,
+	edition: Option<String>,
+}
+
+pub async fn tag_movie(args: TagMovieArgs) -> anyhow::Result<()> {
+	set_unattended(args.yes);
+	set_language(args.language)?;
+	set_sdh_annotations(args.sdh.or(config().sdh).unwrap_or_default());
+	if let Some(ref library) = args.library {
+		if !library.is_dir() {
+			return Err(anyhow!(
+				"Library folder {} doesn't exist",
+				library.display()
+			));
+		}
+	}
+
+	let tmdb_client = tmdb_async::Client::new(TMDB_API_KEY.clone());
+	let movie = get_movie(&tmdb_client, args.tmdb_id).await?;
+	let manually_select_subs = if is_unattended() {
+		false
+	} else {
+		interact(|| {
+			Confirm::with_theme(&*THEME)
+				.with_prompt("Would you like to select subtitles manually?")
+				.interact()
+		})
+		.await?
+	};
+	let reference = Transcript::new(
+		&get_subtitles(movie.id(), manually_select_subs)
+			.await
+			.context("Couldn't get subtitles for the movie")?,
+	);
+	let Some(mut files) = get_ripped_subtitles().await? else {
+		return Ok(());
+	};
+	let ocr_correction = args.ocr_correction.or(config().ocr_correction);
+	correct_ripped(
+		&mut files,
+		&[&reference],
+		ocr_correction.unwrap_or_default(),
+	)
+	.await?;
+
+	let matches: Vec<Match> = files
+		.par_iter()
+		.map(|(_, transcript)| Match::compare(&reference, transcript, None))
+		.collect();
+	let durations: Vec<Option<Duration>> = files
+		.iter()
+		.map(|(path, _)| get_duration(&path.with_extension("mkv")))
+		.collect();
+
+	// Anything that shares most of its dialogue with the movie is a version of it.
+	// Versions with the same runtime are the same cut, so only the best one is kept.
+	let mut candidates: Vec<usize> = (0..files.len())
+		.filter(|idx| matches[*idx].exact && matches[*idx].similarity() >= 1.0 - UNASSIGNED_COST)
+		.collect();
+	candidates.sort_by(|a, b| {
+		matches[*b]
+			.similarity()
+			.total_cmp(&matches[*a].similarity())
+	});
+	let mut cuts = Vec::<Cut>::new();
+	let mut duplicates = Vec::<(usize, usize)>::new();
+	for file_idx in candidates {
+		let duration = durations[file_idx];
+		let same_cut = cuts.iter().find(|cut| match (cut.duration, duration) {
+			(Some(a), Some(b)) => a.abs_diff(b) <= ANGLE_TOLERANCE,
+			_ => false,
+		});
+		match same_cut {
+			Some(cut) => duplicates.push((file_idx, cut.file_idx)),
+			None => cuts.push(Cut {
+				file_idx,
+				duration,
+				edition: None,
+			}),
+		}
+	}
+
+	// The main feature is whichever cut is closest to the runtime listed on TMDB, which
+	// is usually the theatrical release. Other cuts need enough extra (or missing)
+	// runtime and dialogue to be considered a different edition.
+	let runtime = match movie.runtime() {
+		0 => None,
+		minutes => Some(Duration::from_secs(minutes as u64 * 60)),
+	};
+	// Cuts whose runtime couldn't be read go last, since there's no telling how they
+	// compare
+	cuts.sort_by_key(|cut| match (cut.duration, runtime) {
+		(Some(duration), Some(runtime)) => duration.abs_diff(runtime),
+		(Some(_), None) => Duration::ZERO,
+		(None, _) => Duration::MAX,
+	});
+	let mut editions = Vec::<Cut>::new();
+	if let Some(main) = cuts.first() {
+		let main_length = files[main.file_idx].1.length as f64;
+		for cut in &cuts {
+			let length = files[cut.file_idx].1.length as f64;
+			let edition = if cut.file_idx == main.file_idx {
+				// The main feature only gets an edition if it's clearly longer than TMDB
+				// says, and has the dialogue to go with it
+				let longer = matches!(
+					(cut.duration, runtime),
+					(Some(duration), Some(runtime)) if duration > runtime + EDITION_MIN_DIFFERENCE
+				);
+				if longer && length > reference.length as f64 * (1.0 + EDITION_MIN_DIALOGUE) {
+					Some("Extended Edition")
+				} else {
+					None
+				}
+			} else {
+				// Without both runtimes, the dialogue alone has to tell the cuts apart
+				let runtimes = cut.duration.zip(main.duration);
+				let longer = runtimes.is_none_or(|(duration, main_duration)| {
+					duration > main_duration + EDITION_MIN_DIFFERENCE
+				});
+				let shorter = runtimes.is_none_or(|(duration, main_duration)| {
+					duration + EDITION_MIN_DIFFERENCE < main_duration
+				});
+				if longer && length > main_length * (1.0 + EDITION_MIN_DIALOGUE) {
+					Some("Extended Edition")
+				} else if shorter && length < main_length * (1.0 - EDITION_MIN_DIALOGUE) {
+					Some("Theatrical Cut")
+				} else {
+					duplicates.push((cut.file_idx, main.file_idx));
+					continue;
+				}
+			};
+			// Two cuts can't share a name, so tell them apart by their runtime, or by
+			// number if it's unknown
+			let mut edition = edition.map(String::from);
+			if let Some(label) = &edition {
+				if editions.iter().any(|other| other.edition == edition) {
+					let suffix = cut
+						.duration
+						.map_or_else(|| (editions.len() + 1).to_string(), describe_runtime);
+					edition = Some(format!("{label} {suffix}"));
+				}
+			}
+			editions.push(Cut {
+				file_idx: cut.file_idx,
+				duration: cut.duration,
+				edition,
+			});
+		}
+	}
+
+	// Show the whole plan before touching anything on disk
+	let mut plan = Plan::default();
+	let folder_name = movie_name(&movie);
+	let extras_root = match args.library {
+		Some(ref library) => library.join(&folder_name),
+		None => PathBuf::from("."),
+	};
+	let typical = runtime.or_else(|| editions.first().and_then(|cut| cut.duration));
+	println!("Proposed changes:");
+	for (file_idx, (file_path, transcript)) in files.iter().enumerate() {
+		let mkv_file = file_path.with_extension("mkv");
+		let Some(cut) = editions.iter().find(|cut| cut.file_idx == file_idx) else {
+			let original = duplicates
+				.iter()
+				.find(|(duplicate, _)| *duplicate == file_idx)
+				.map(|(_, original)| files[*original].0.with_extension("mkv"));
+			if args.leave_unmatched {
+				match original {
+					Some(original) => {
+						println!("{:?} => ??? (Same cut as {:?})", &mkv_file, original)
+					}
+					None => println!("{:?} => ??? (No match found)", &mkv_file),
+				}
+				plan.unmatched.push(mkv_file);
+				continue;
+			}
+			let profile = TitleProfile {
+				duration: durations[file_idx],
+				words: transcript.length,
+				duplicate: original.is_some(),
+			};
+			let kind = classify(&profile, typical);
+			let target =
+				extra_destination(kind, &extras_root, args.quarantine.as_deref(), &mkv_file);
+			let similarity = matches[file_idx].similarity();
+			let confidence = extra_confidence(similarity, original.is_some());
+			let description = match original {
+				Some(original) if kind == ExtraKind::Duplicate => {
+					format!("same cut as {:?}", original)
+				}
+				_ => String::from(kind.describe()),
+			};
+			println!(
+				"{:?} => {:?} ({})\n    similarity:       {}\n    confidence:       {:.2}\n    runtime:          {}",
+				&mkv_file,
+				&target,
+				description,
+				matches[file_idx].describe_similarity(),
+				confidence,
+				profile
+					.duration
+					.map_or_else(|| String::from("unknown"), describe_runtime),
+			);
+			plan.entries.push(PlanEntry {
+				source: mkv_file,
+				target,
+				episode_ids: Vec::new(),
+				sidecars: sidecars(file_path),
+				similarity,
+				confidence,
+				replace: args.replace,
+				extra: Some(kind),
+			});
+			continue;
+		};
+
+		let best = &matches[file_idx];
+		let confidence = best.confidence(None);
+		let mut file_name = folder_name.clone();
+		if let Some(ref edition) = cut.edition {
+			file_name.push_str(&format!(" {{edition-{}}}", sanitize(edition)));
+		}
+		file_name.push_str(".mkv");
+		let filename = match args.library {
+			Some(ref library) => library.join(&folder_name).join(file_name),
+			None => PathBuf::from(file_name),
+		};
+		println!(
+			"{:?} => {:?}\n    similarity:       {}\n    confidence:       {:.2}\n    timing:           {}\n    runtime:          {} (TMDB: {})\n    dialogue:         {} words (reference: {})",
+			&mkv_file,
+			&filename,
+			best.describe_similarity(),
+			confidence,
+			describe_timing(&best.timing),
+			cut.duration.map_or_else(|| String::from("unknown"), describe_runtime),
+			runtime.map_or_else(|| String::from("unknown"), describe_runtime),
+			files[file_idx].1.length,
+			reference.length,
+		);
+		if let Some(sdh) = describe_sdh(&reference, &files[file_idx].1) {
+			println!("    sdh:              {sdh}");
+		}
+		plan.entries.push(PlanEntry {
+			source: mkv_file,
+			target: filename,
+			episode_ids: vec![movie.id()],
+			sidecars: sidecars(file_path),
+			similarity: best.similarity(),
+			confidence,
+			replace: args.replace,
+			extra: None,
+		});
+	}
+
+	if let Some(ref plan_path) = args.plan {
+		plan.write(plan_path).await?;
+		println!("Wrote plan to {:?}", plan_path);
+		return Ok(());
+	}
+	plan.confirm_and_apply(args.auto_accept, &mut Vec::new())
+		.await?;
+
+	return Ok(());
+}
+
+/// Name of the movie as Plex expects it, such as `Title (Year) {tmdb-ID}`
+fn movie_name(movie: &Movie) -> String {
+	let mut name = sanitize(movie.title());
+	if let Some(release_date) = movie.release_date() {
+		name.push_str(&format!(" ({})", release_date.year()));
+	}
+	name.push_str(&format!(" {{tmdb-{}}}", movie.id()));
+	return name;
+}
+
+pub async fn get_movie(
+	tmdb_client: &tmdb_async::Client,
+	tmdb_id: Option<u32>,
+) -> anyhow::Result<Movie> {
+	if let Some(tmdb_id) = tmdb_id {
+		return tmdb_client
+			.movie_by_id(tmdb_id, false, false)
+			.await
+			.context("Couldn't get movie");
+	}
+
+	// Ask the user for search query
+	ensure_interactive("--tmdb-id")?;
+	let input_title: String = interact(|| {
+		dialoguer::Input::with_theme(&*THEME)
+			.with_prompt("Title")
+			.interact_text()
+	})
+	.await?;
+	let titles = tmdb_client
+		.movie_search(&encode(&input_title), None)
+		.await?;
+	if titles.results().is_empty() {
+		return Err(anyhow!("No movies found for {input_title:?}"));
+	}
+
+	// Ask the user which search result to use
+	let title_descriptions: Vec<String> = titles
+		.results()
+		.iter()
+		.map(|title| match title.release_date() {
+			Some(release_date) => {
+				format!("{}: {} ({})", title.id(), title.title(), release_date)
+			}
+			None => format!("{}: {}", title.id(), title.title()),
+		})
+		.collect();
+	let selected_title_index: usize = interact(move || {
+		Select::with_theme(&*THEME)
+			.items(&title_descriptions)
+			.with_prompt("Please select the desired result")
+			.default(0)
+			.interact()
+	})
+	.await?;
+	return tmdb_client
+		.movie_by_id(titles.results()[selected_title_index].id(), false, false)
+		.await
+		.context("Couldn't get movie");
+}
@@ -0,0 +1,462 @@
+use std::path::PathBuf;
+
+use lazy_regex::regex;
+use tmdb_async::Episode;
+
+/// Built-in templates, selected by passing their name in place of a template
+const PRESETS: [(&str, &str); 4] = [
+	("flat", "S{season:02}E{episode:02} - {title}"),
+	(
+		"plex",
+		"{show} ({year}) {{tmdb-{tmdb_id}}}/Season {season:02}/{show} ({year}) - S{season:02}E{episode:02} - {title}",
+	),
+	(
+		"jellyfin",
+		"{show} ({year}) [tmdbid-{tmdb_id}]/Season {season:02}/{show} S{season:02}E{episode:02} - {title}",
+	),
+	(
+		"kodi",
+		"{show} ({year})/Season {season:02}/{show} S{season:02}E{episode:02} - {title}",
+	),
+];
+
+/// Preset used when no template was given anywhere
+pub const DEFAULT_TEMPLATE: &str = "flat";
+
+/// Folders that files are sorted into within a library, for templates that don't
+/// create any of their own
+pub const LIBRARY_FOLDERS: &str = "{show} ({year})/Season {season:02}";
+
+/// What's known about the show being tagged, for filling in templates
+#[derive(Debug, Clone)]
+pub struct ShowInfo {
+	pub name: String,
+	pub year: Option<i32>,
+	pub tmdb_id: u32,
+	pub tvdb_id: Option<u32>,
+}
+
+/// Describes where a tagged file should go, relative to the output directory.
+///
+/// Fields are written as `{field}` or `{field:options}`, where options are separated
+/// by colons and can be a width to zero-pad numbers to (`{season:02}`), or one of
+/// `upper`, `lower` or `title` to change the case of text. Use `{{` and `}}` for
+/// literal braces, and `/` to separate folders. The extension is added automatically.
+#[derive(Debug, Clone)]
+pub struct Template {
+	parts: Vec<Part>,
+}
+
+#[derive(Debug, Clone)]
+enum Part {
+	Literal(String),
+	Field {
+		field: Field,
+		width: usize,
+		case: Case,
+	},
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+	Show,
+	Year,
+	Season,
+	Episode,
+	Title,
+	TmdbId,
+	TvdbId,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Case {
+	Unchanged,
+	Upper,
+	Lower,
+	Title,
+}
+
+impl Template {
+	/// Parses a template, or looks up a preset by name
+	pub fn parse(template: &str) -> Result<Self, String> {
+		let template = PRESETS
+			.iter()
+			.find(|(name, _)| name.eq_ignore_ascii_case(template))
+			.map_or(template, |(_, preset)| preset);
+
+		let mut parts = Vec::<Part>::new();
+		let mut literal = String::new();
+		let mut chars = template.chars();
+		while let Some(c) = chars.next() {
+			match c {
+				'{' | '}' if chars.as_str().starts_with(c) => {
+					chars.next();
+					literal.push(c);
+				}
+				'}' => return Err(String::from("unmatched '}' in template")),
+				'{' => {
+					let Some((field, rest)) = chars.as_str().split_once('}') else {
+						return Err(String::from("unmatched '{' in template"));
+					};
+					if !literal.is_empty() {
+						parts.push(Part::Literal(std::mem::take(&mut literal)));
+					}
+					parts.push(parse_field(field)?);
+					chars = rest.chars();
+				}
+				_ => literal.push(c),
+			}
+		}
+		if !literal.is_empty() {
+			parts.push(Part::Literal(literal));
+		}
+		return Ok(Self { parts });
+	}
+
+	/// Whether rendering the template creates any folders
+	pub fn creates_folders(&self) -> bool {
+		return self
+			.parts
+			.iter()
+			.any(|part| matches!(part, Part::Literal(text) if text.contains('/')));
+	}
+
+	/// Places everything this template creates inside of the folders from another
+	pub fn nested_in(self, folders: &Template) -> Self {
+		let mut parts = folders.parts.clone();
+		parts.push(Part::Literal(String::from("/")));
+		parts.extend(self.parts);
+		return Self { parts };
+	}
+
+	/// Whether the template refers to `field`, so it's only looked up when needed
+	pub fn uses(&self, field: Field) -> bool {
+		return self
+			.parts
+			.iter()
+			.any(|part| matches!(part, Part::Field { field: used, .. } if *used == field));
+	}
+
+	/// Builds the path for a file containing one or more consecutive episodes, with the
+	/// given extension. Files with several episodes are named like `S01E01-E02`, with
+	/// whatever comes right before `{episode}` repeated for each of them.
+	pub fn render(&self, show: &ShowInfo, episodes: &[&Episode], extension: &str) -> PathBuf {
+		let mut rendered = String::new();
+		for part in &self.parts {
+			match part {
+				Part::Literal(text) => rendered.push_str(text),
+				Part::Field {
+					field: Field::Episode,
+					width,
+					..
+				} => {
+					let prefix: String = rendered
+						.chars()
+						.rev()
+						.take_while(char::is_ascii_alphabetic)
+						.collect::<Vec<char>>()
+						.into_iter()
+						.rev()
+						.collect();
+					let numbers: Vec<String> = episodes
+						.iter()
+						.map(|episode| format!("{:0width$}", episode.episode_number))
+						.collect();
+					rendered.push_str(&numbers.join(&format!("-{prefix}")));
+				}
+				Part::Field { field, width, case } => {
+					rendered
+						.push_str(&render_field(*field, *width, *case, show, episodes).unwrap());
+				}
+			}
+		}
+
+		// Titles often contain dots, so the extension is tacked on rather than set
+		let mut components: Vec<String> = rendered
+			.split('/')
+			.map(tidy_component)
+			.filter(|component| !component.is_empty())
+			.collect();
+		if let Some(file_name) = components.last_mut() {
+			file_name.push('.');
+			file_name.push_str(extension);
+		}
+		return components.iter().collect();
+	}
+
+	/// Builds the first folder the template creates, if it's the same for every episode
+	/// of the show. Extras for the show are kept in here.
+	pub fn show_folder(&self, show: &ShowInfo) -> Option<PathBuf> {
+		let mut rendered = String::new();
+		for part in &self.parts {
+			match part {
+				Part::Literal(text) => match text.split_once('/') {
+					Some((before, _)) => {
+						rendered.push_str(before);
+						let folder = tidy_component(&rendered);
+						return (!folder.is_empty()).then(|| PathBuf::from(folder));
+					}
+					None => rendered.push_str(text),
+				},
+				Part::Field { field, width, case } => {
+					rendered.push_str(&render_field(*field, *width, *case, show, &[])?);
+				}
+			}
+		}
+		return None;
+	}
+}
+
+/// Fills in a single field, or returns `None` if it needs an episode and there isn't one
+fn render_field(
+	field: Field,
+	width: usize,
+	case: Case,
+	show: &ShowInfo,
+	episodes: &[&Episode],
+) -> Option<String> {
+	let number = |number: Option<u64>| match number {
+		Some(number) => format!("{number:0width$}"),
+		None => String::new(),
+	};
+	let first = episodes.first();
+	let value = match field {
+		Field::Show => show.name.clone(),
+		Field::Year => number(show.year.map(|year| year as u64)),
+		Field::Season => number(Some(first?.season_number.into())),
+		Field::Episode => number(Some(first?.episode_number.into())),
+		Field::Title => combined_title(episodes)?,
+		Field::TmdbId => number(Some(show.tmdb_id.into())),
+		Field::TvdbId => number(show.tvdb_id.map(u64::from)),
+	};
+	return Some(sanitize(&case.apply(&value)));
+}
+
+/// Title of one or more episodes in a single file. Parts of a story, such as
+/// "Pilot (1)" and "Pilot (2)", share a title, and anything else is listed in full.
+fn combined_title(episodes: &[&Episode]) -> Option<String> {
+	if let [episode] = episodes {
+		return Some(episode.name.clone());
+	}
+	let part = regex!(r"(?i)[\s,:-]*(?:\(\d+\)|\(?part\s+(?:\d+|one|two|three|i+)\)?)$");
+	let titles: Vec<&str> = episodes
+		.iter()
+		.map(|episode| episode.name.as_str())
+		.collect();
+	let base = part.replace(titles.first()?, "");
+	if titles
+		.iter()
+		.all(|title| part.is_match(title) && part.replace(title, "") == base)
+	{
+		return Some(base.into_owned());
+	}
+	return Some(titles.join(" & "));
+}
+
+fn parse_field(spec: &str) -> Result<Part, String> {
+	let mut options = spec.split(':').map(str::trim);
+	let name = options.next().unwrap_or_default();
+	let field = match name {
+		"show" => Field::Show,
+		"year" => Field::Year,
+		"season" => Field::Season,
+		"episode" => Field::Episode,
+		"title" => Field::Title,
+		"tmdb_id" => Field::TmdbId,
+		"tvdb_id" => Field::TvdbId,
+		_ => return Err(format!("unknown template field {name:?}")),
+	};
+	let mut width = 0;
+	let mut case = Case::Unchanged;
+	for option in options {
+		if let Ok(parsed) = option.parse() {
+			width = parsed;
+			continue;
+		}
+		case = match option {
+			"upper" => Case::Upper,
+			"lower" => Case::Lower,
+			"title" => Case::Title,
+			_ => return Err(format!("unknown option {option:?} for {name:?}")),
+		};
+	}
+	return Ok(Part::Field { field, width, case });
+}
+
+impl Case {
+	fn apply(&self, value: &str) -> String {
+		return match self {
+			Case::Unchanged => value.to_owned(),
+			Case::Upper => value.to_uppercase(),
+			Case::Lower => value.to_lowercase(),
+			Case::Title => {
+				let mut title = String::with_capacity(value.len());
+				let mut word_start = true;
+				for c in value.chars() {
+					if word_start {
+						title.extend(c.to_uppercase());
+					} else {
+						title.extend(c.to_lowercase());
+					}
+					word_start = c.is_whitespace();
+				}
+				title
+			}
+		};
+	}
+}
+
+/// Keeps names from TMDB from creating folders of their own, or using characters
+/// that aren't allowed on some filesystems.
+pub fn sanitize(value: &str) -> String {
+	return value
+		.replace(": ", " - ")
+		.replace(['/', '\\', ':'], "-")
+		.replace(['<', '>', '"', '|', '?', '*'], "");
+}
+
+/// Cleans up what's left of a folder or file name after a field came up empty
+fn tidy_component(component: &str) -> String {
+	let component = regex!(r"\(\s*\)|\[(?:\w+-)?\s*\]|\{(?:\w+-)?\s*\}").replace_all(component, "");
+	let component = regex!(r"\s{2,}").replace_all(&component, " ");
+	return component
+		.trim_matches(|c: char| c.is_whitespace() || c == '.')
+		.to_owned();
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::path::Path;
+
+	fn show(year: Option<i32>) -> ShowInfo {
+		return ShowInfo {
+			name: String::from("Doctor Who"),
+			year,
+			tmdb_id: 57243,
+			tvdb_id: None,
+		};
+	}
+
+	fn episode(episode_number: u32, name: &str) -> Episode {
+		return Episode {
+			air_date: None,
+			episode_number,
+			id: episode_number,
+			name: name.to_owned(),
+			overview: None,
+			production_code: None,
+			runtime: None,
+			season_number: 1,
+			show_id: 57243,
+			still_path: None,
+			vote_average: None,
+			vote_count: None,
+			crew: None,
+		};
+	}
+
+	fn render(template: &str, episodes: &[&Episode]) -> PathBuf {
+		return Template::parse(template)
+			.unwrap()
+			.render(&show(Some(2005)), episodes, "mkv");
+	}
+
+	#[test]
+	fn doubled_braces_are_literal() {
+		let rose = episode(1, "Rose");
+		assert_eq!(
+			render("{{{title}}} }}{{", &[&rose]),
+			Path::new("{Rose} }{.mkv")
+		);
+		assert!(Template::parse("{title").is_err());
+		assert!(Template::parse("title}").is_err());
+	}
+
+	#[test]
+	fn unknown_fields_and_options_are_errors() {
+		assert!(Template::parse("{name}").is_err());
+		assert!(Template::parse("{title:bold}").is_err());
+		assert!(Template::parse("{season:02:upper}").is_ok());
+	}
+
+	#[test]
+	fn width_and_case_options() {
+		let episode = episode(7, "the LONG game");
+		assert_eq!(
+			render("{season:03}x{episode:2} {title:upper}", &[&episode]),
+			Path::new("001x07 THE LONG GAME.mkv")
+		);
+		assert_eq!(
+			render("{show:lower} - {title:title}", &[&episode]),
+			Path::new("doctor who - The Long Game.mkv")
+		);
+	}
+
+	#[test]
+	fn several_episodes_in_one_file() {
+		let first = episode(4, "Aliens of London (1)");
+		let second = episode(5, "World War Three (2)");
+		assert_eq!(
+			render("flat", &[&first, &second]),
+			Path::new("S01E04-E05 - Aliens of London (1) & World War Three (2).mkv")
+		);
+		let first = episode(12, "Bad Wolf, Part 1");
+		let second = episode(13, "Bad Wolf, Part 2");
+		assert_eq!(
+			render("{season}x{episode} {title}", &[&first, &second]),
+			Path::new("1x12-x13 Bad Wolf.mkv")
+		);
+	}
+
+	#[test]
+	fn names_are_made_safe_for_filesystems() {
+		assert_eq!(sanitize("Who: Me/You?"), "Who - Me-You");
+		assert_eq!(sanitize(r#"A\B:C<"D">|*"#), "A-B-CD");
+		let episode = episode(9, "The Empty Child: Part 1/2");
+		assert_eq!(
+			render("kodi", &[&episode]),
+			Path::new(
+				"Doctor Who (2005)/Season 01/Doctor Who S01E09 - The Empty Child - Part 1-2.mkv"
+			)
+		);
+	}
+
+	#[test]
+	fn empty_fields_are_tidied_away() {
+		assert_eq!(tidy_component("Show ( ) [tmdbid-] {tvdb-}. "), "Show");
+		assert_eq!(tidy_component("  A   B.."), "A B");
+		let rose = episode(1, "Rose");
+		let template = Template::parse("jellyfin").unwrap();
+		assert_eq!(
+			template.render(&show(None), &[&rose], "srt"),
+			Path::new("Doctor Who [tmdbid-57243]/Season 01/Doctor Who S01E01 - Rose.srt")
+		);
+	}
+
+	#[test]
+	fn show_folders_of_presets() {
+		let folder = |preset: &str, year: Option<i32>| {
+			return Template::parse(preset).unwrap().show_folder(&show(year));
+		};
+		assert_eq!(folder("flat", Some(2005)), None);
+		assert_eq!(
+			folder("plex", Some(2005)),
+			Some(PathBuf::from("Doctor Who (2005) {tmdb-57243}"))
+		);
+		assert_eq!(
+			folder("plex", None),
+			Some(PathBuf::from("Doctor Who {tmdb-57243}"))
+		);
+		assert_eq!(
+			folder("jellyfin", Some(2005)),
+			Some(PathBuf::from("Doctor Who (2005) [tmdbid-57243]"))
+		);
+		assert_eq!(
+			folder("kodi", Some(2005)),
+			Some(PathBuf::from("Doctor Who (2005)"))
+		);
+		// A folder that depends on the episode isn't the same for the whole show
+		assert_eq!(folder("Season {season}/{title}", Some(2005)), None);
+	}
+}
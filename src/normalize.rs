@@ -0,0 +1,394 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use clap::ValueEnum;
+use lazy_regex::{regex, regex_captures};
+use serde::Deserialize;
+use unicode_normalization::UnicodeNormalization;
+
+/// What to do with the annotations in subtitles for the deaf and hard of hearing
+/// (SDH), such as "[DOOR SLAMS]", "♪ lyrics ♪" and "JOHN:" speaker labels
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SdhAnnotations {
+	/// Remove them, since disc subtitles often don't have them
+	#[default]
+	Strip,
+	/// Compare them like any other text
+	Keep,
+}
+
+static STRIP_SDH: AtomicBool = AtomicBool::new(true);
+
+/// Sets whether transcripts have SDH annotations removed, for the rest of the process
+pub fn set_sdh_annotations(annotations: SdhAnnotations) {
+	STRIP_SDH.store(annotations == SdhAnnotations::Strip, Ordering::Relaxed);
+}
+
+pub fn strips_sdh() -> bool {
+	return STRIP_SDH.load(Ordering::Relaxed);
+}
+
+/// Removes SDH annotations from subtitles, returning what's left and how many were
+/// removed. Cues that had nothing but annotations are removed entirely, so that they
+/// don't throw off the timing alignment either.
+pub fn strip_sdh(subs: &str) -> (String, usize) {
+	let mut annotations = 0;
+	let mut stripped = String::with_capacity(subs.len());
+	let mut cue = Vec::<String>::new();
+	let mut had_text = false;
+	let mut has_text = false;
+	for line in subs.lines().chain([""]) {
+		if line.trim().is_empty() {
+			if has_text || !had_text {
+				for line in cue.drain(..) {
+					stripped.push_str(&line);
+					stripped.push('\n');
+				}
+				stripped.push('\n');
+			}
+			cue.clear();
+			(had_text, has_text) = (false, false);
+			continue;
+		}
+		if line.contains("-->") || line.trim().chars().all(|c| c.is_ascii_digit()) {
+			cue.push(line.to_owned());
+			continue;
+		}
+		had_text = true;
+		let mut line = line.to_owned();
+		for pattern in [
+			regex!(r"\[[^\]]*\]|\([^)]*\)|（[^）]*）|【[^】]*】"),
+			regex!(r"[♪♫].*?(?:[♪♫]|$)|^\s*#.*#\s*$"),
+		] {
+			annotations += pattern.find_iter(&line).count();
+			line = pattern.replace_all(&line, "").into_owned();
+		}
+		let speaker = regex!(r"^(\s*-?\s*)\p{Lu}[\p{Lu}\p{N} .'-]+:(?:\s+|$)");
+		if speaker.is_match(&line) {
+			annotations += 1;
+			line = speaker.replace(&line, "$1").into_owned();
+		}
+		if line.chars().any(char::is_alphanumeric) {
+			has_text = true;
+			cue.push(line);
+		}
+	}
+	return (stripped, annotations);
+}
+
+/// Reduces subtitles to the words that were spoken, so that the same line compares
+/// equal however it was typed, styled or recognized:
+///
+/// 1. Cue numbers, timings and formatting tags are removed
+/// 2. Compatibility characters, such as full-width letters and ligatures, are
+///    replaced by their plain forms (NFKC)
+/// 3. Case is folded
+/// 4. Quotes, apostrophes and dashes are unified, and other symbols dropped
+/// 5. Numbers are written as digits, and ordinals as digits with a suffix
+/// 6. Chinese and Japanese have every character split into a word of its own
+///
+/// Only `.`, `,`, `?` and `!` are kept as punctuation, attached to the word before them.
+pub fn normalize_subtitles(subs: &str) -> String {
+	let text = regex!(r"<\s*[^>]*>|\{\\[^}]*\}|^.*-->.*$|^[0-9]+\r?$"m).replace_all(subs, " ");
+	let text: String = text.nfkc().collect();
+	let text = unify_punctuation(&fold_case(&text));
+	let words: Vec<String> = text
+		.split_whitespace()
+		.map(canonicalize_word)
+		.filter(|word| !word.is_empty())
+		.collect();
+	return separate_unspaced(&words.join(" "));
+}
+
+/// Lowercases text, along with the few letters whose folded form is something else
+fn fold_case(text: &str) -> String {
+	let mut folded = String::with_capacity(text.len());
+	for c in text.chars().flat_map(char::to_lowercase) {
+		match c {
+			'ß' => folded.push_str("ss"),
+			'ς' => folded.push('σ'),
+			_ => folded.push(c),
+		}
+	}
+	return folded;
+}
+
+fn unify_punctuation(text: &str) -> String {
+	let mut unified = String::with_capacity(text.len());
+	for c in text.chars() {
+		match c {
+			// Whether an apostrophe or hyphen was typed, or which kind, varies between
+			// transcripts of the same line, so words are joined across them
+			'\'' | '`' | '´' | '‘' | '’' | '‛' | '-' | '‐' | '‑' => {}
+			'。' | '．' => unified.push('.'),
+			'、' | '，' => unified.push(','),
+			'.' | ',' | '?' | '!' => unified.push(c),
+			_ if c.is_alphanumeric() => unified.push(c),
+			// Quotes, dashes between words, music notes and so on
+			_ => unified.push(' '),
+		}
+	}
+	return unified;
+}
+
+const CARDINALS: [&str; 20] = [
+	"zero",
+	"one",
+	"two",
+	"three",
+	"four",
+	"five",
+	"six",
+	"seven",
+	"eight",
+	"nine",
+	"ten",
+	"eleven",
+	"twelve",
+	"thirteen",
+	"fourteen",
+	"fifteen",
+	"sixteen",
+	"seventeen",
+	"eighteen",
+	"nineteen",
+];
+
+const ORDINALS: [&str; 20] = [
+	"zeroth",
+	"first",
+	"second",
+	"third",
+	"fourth",
+	"fifth",
+	"sixth",
+	"seventh",
+	"eighth",
+	"ninth",
+	"tenth",
+	"eleventh",
+	"twelfth",
+	"thirteenth",
+	"fourteenth",
+	"fifteenth",
+	"sixteenth",
+	"seventeenth",
+	"eighteenth",
+	"nineteenth",
+];
+
+const TENS: [(&str, &str); 8] = [
+	("twenty", "twentieth"),
+	("thirty", "thirtieth"),
+	("forty", "fortieth"),
+	("fifty", "fiftieth"),
+	("sixty", "sixtieth"),
+	("seventy", "seventieth"),
+	("eighty", "eightieth"),
+	("ninety", "ninetieth"),
+];
+
+/// Writes a number, or an ordinal, the same way whether it was spelled out or not.
+/// Only English numbers up to 99 are recognized in words.
+fn canonicalize_word(word: &str) -> String {
+	let core = word.trim_matches(|c| matches!(c, '.' | ',' | '?' | '!'));
+	if core.is_empty() {
+		return String::new();
+	}
+	let start = word.find(core).unwrap();
+	let (before, after) = (&word[..start], &word[start + core.len()..]);
+
+	let canonical = if regex!(r"^\d{1,3}(?:[.,]\d{3})+$").is_match(core) {
+		core.replace(['.', ','], "")
+	} else if let Some((_, number, _)) = regex_captures!(r"^(\d+)(st|nd|rd|th)$", core) {
+		ordinal(number.parse().unwrap_or(u64::MAX))
+	} else if let Some((number, is_ordinal)) = parse_number_word(core) {
+		match is_ordinal {
+			true => ordinal(number),
+			false => number.to_string(),
+		}
+	} else {
+		core.to_owned()
+	};
+	return format!("{before}{canonical}{after}");
+}
+
+/// Reads a number written as a single word, such as "seven", "twentyone" or
+/// "fortieth", returning it and whether it was an ordinal
+fn parse_number_word(word: &str) -> Option<(u64, bool)> {
+	let units = |word: &str| -> Option<(u64, bool)> {
+		if let Some(idx) = CARDINALS.iter().position(|cardinal| *cardinal == word) {
+			return Some((idx as u64, false));
+		}
+		let idx = ORDINALS.iter().position(|ordinal| *ordinal == word)?;
+		return Some((idx as u64, true));
+	};
+	if let Some(number) = units(word) {
+		return Some(number);
+	}
+	for (idx, (cardinal, ordinal)) in TENS.iter().enumerate() {
+		let tens = (idx as u64 + 2) * 10;
+		if word == *ordinal {
+			return Some((tens, true));
+		}
+		let Some(rest) = word.strip_prefix(cardinal) else {
+			continue;
+		};
+		if rest.is_empty() {
+			return Some((tens, false));
+		}
+		return match units(rest) {
+			Some((unit, is_ordinal)) if (1..10).contains(&unit) => Some((tens + unit, is_ordinal)),
+			_ => None,
+		};
+	}
+	return None;
+}
+
+fn ordinal(number: u64) -> String {
+	let suffix = match (number % 10, number % 100) {
+		(_, 11..=13) => "th",
+		(1, _) => "st",
+		(2, _) => "nd",
+		(3, _) => "rd",
+		_ => "th",
+	};
+	return format!("{number}{suffix}");
+}
+
+/// Whether a character is Chinese or Japanese, which are written without spaces
+/// between words
+fn is_unspaced(c: char) -> bool {
+	return matches!(c,
+		'\u{3040}'..='\u{30FF}' // Hiragana and Katakana
+		| '\u{3400}'..='\u{4DBF}' // CJK Unified Ideographs Extension A
+		| '\u{4E00}'..='\u{9FFF}' // CJK Unified Ideographs
+		| '\u{F900}'..='\u{FAFF}' // CJK Compatibility Ideographs
+		| '\u{20000}'..='\u{2FA1F}' // Supplementary ideographs
+	);
+}
+
+/// Without spaces to go by, every character of such a script is treated as a word
+fn separate_unspaced(text: &str) -> String {
+	let mut separated = String::with_capacity(text.len() * 2);
+	let mut after_unspaced = false;
+	for c in text.chars() {
+		let unspaced = is_unspaced(c);
+		// Punctuation stays with the character before it, like it does with words
+		let punctuation = matches!(c, '.' | ',' | '?' | '!' | ' ');
+		if (unspaced || after_unspaced && !punctuation)
+			&& !separated.is_empty()
+			&& !separated.ends_with(' ')
+		{
+			separated.push(' ');
+		}
+		separated.push(c);
+		after_unspaced = unspaced;
+	}
+	return separated;
+}
+
+#[cfg(test)]
+mod tests {
+	// Golden tests for transcript normalization. Each `.srt` file in
+	// `tests/normalization` goes through the default pipeline and is compared against the
+	// `.txt` file of the same name. Those with a `.txt` file in `tests/normalization/sdh`
+	// are also compared against that with their SDH annotations stripped first. After an
+	// intended change, run with `UPDATE_GOLDEN=1` to rewrite the expected output, and
+	// review the difference along with the change.
+
+	use super::*;
+	use std::{
+		fs,
+		path::{Path, PathBuf},
+	};
+
+	/// Runs the `.srt` files of the corpus through `pipeline`, and compares the results
+	/// against their golden files in `goldens`. Outside of the corpus folder itself,
+	/// only the files with a golden file are checked.
+	fn check_corpus(goldens: &Path, pipeline: impl Fn(&str) -> String) {
+		let update = std::env::var_os("UPDATE_GOLDEN").is_some();
+		let mut inputs: Vec<_> = fs::read_dir(corpus())
+			.unwrap()
+			.map(|item| item.unwrap().path())
+			.filter(|path| path.extension().is_some_and(|extension| extension == "srt"))
+			.collect();
+		inputs.sort_unstable();
+
+		let mut checked = 0;
+		let mut mismatches = Vec::<String>::new();
+		for input in inputs {
+			let golden = goldens
+				.join(input.file_name().unwrap())
+				.with_extension("txt");
+			if goldens != corpus() && !golden.exists() {
+				continue;
+			}
+			checked += 1;
+			let normalized = pipeline(&fs::read_to_string(&input).unwrap()) + "\n";
+			if update {
+				fs::write(&golden, &normalized).unwrap();
+				continue;
+			}
+			let expected = fs::read_to_string(&golden).unwrap_or_default();
+			if normalized != expected {
+				mismatches.push(format!(
+					"{}\n  expected: {}\n  actual:   {}",
+					golden.display(),
+					expected.trim_end(),
+					normalized.trim_end()
+				));
+			}
+		}
+		assert!(checked > 0, "{} has no golden files", goldens.display());
+		assert!(
+			mismatches.is_empty(),
+			"normalization changed, rerun with UPDATE_GOLDEN=1 if that was intended:\n{}",
+			mismatches.join("\n")
+		);
+	}
+
+	fn corpus() -> PathBuf {
+		return Path::new(env!("CARGO_MANIFEST_DIR"))
+			.join("tests")
+			.join("normalization");
+	}
+
+	#[test]
+	fn golden_corpus() {
+		check_corpus(&corpus(), normalize_subtitles);
+	}
+
+	#[test]
+	fn golden_sdh_corpus() {
+		check_corpus(&corpus().join("sdh"), |subs| {
+			let (stripped, _) = strip_sdh(subs);
+			return normalize_subtitles(&stripped);
+		});
+	}
+
+	#[test]
+	fn spelled_out_numbers_match_digits() {
+		assert_eq!(
+			normalize_subtitles("Twenty-one guns, the forty-second street"),
+			normalize_subtitles("21 guns, the 42nd street")
+		);
+	}
+
+	#[test]
+	fn case_and_quotes_are_ignored() {
+		assert_eq!(
+			normalize_subtitles("“DON’T go,” she said."),
+			normalize_subtitles("\"Don't go,\" she said.")
+		);
+	}
+
+	#[test]
+	fn cues_with_only_annotations_are_removed() {
+		let (stripped, annotations) = strip_sdh(
+			"1\n00:00:01,000 --> 00:00:02,000\n[THUNDER]\n\n2\n00:00:03,000 --> 00:00:04,000\nHello.\n",
+		);
+		assert_eq!(annotations, 1);
+		assert_eq!(stripped, "2\n00:00:03,000 --> 00:00:04,000\nHello.\n\n");
+	}
+}
@@ -0,0 +1,258 @@
+use std::{
+	collections::HashMap,
+	ffi::OsString,
+	path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use clap::ValueEnum;
+use serde::Deserialize;
+use tokio::fs;
+
+use crate::{
+	autotagger::Transcript, get_st_track::has_image_subtitles, normalize::normalize_subtitles,
+};
+
+/// What to do about OCR errors in subtitles that were extracted as images
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OcrCorrection {
+	/// Correct them before comparing
+	#[default]
+	Apply,
+	/// Correct them, and also write the corrected subtitles next to the title as
+	/// "<title>.corrected.srt" to look over
+	Write,
+	/// Compare the subtitles as they were recognized
+	Off,
+}
+
+/// Characters that Tesseract tends to mistake for one another in subtitle fonts. Only
+/// one kind of mistake is undone per word.
+const CONFUSIONS: &[(&str, &str)] = &[
+	("l", "I"),
+	("I", "l"),
+	("rn", "m"),
+	("m", "rn"),
+	("cl", "d"),
+	("vv", "w"),
+	("1", "l"),
+	("1", "I"),
+	("0", "o"),
+	("5", "s"),
+	("O", "0"),
+	("o", "0"),
+	("l", "1"),
+	("I", "1"),
+];
+
+/// Words that are known to be spoken, with how often, taken from the reference
+/// subtitles so that names and slang are in it too
+pub struct Dictionary {
+	words: HashMap<String, usize>,
+}
+
+impl Dictionary {
+	pub fn new(references: &[&Transcript]) -> Self {
+		let mut words = HashMap::<String, usize>::new();
+		for reference in references {
+			for word in reference.with_annotations().text.split_whitespace() {
+				let word = word.trim_end_matches(['.', ',', '?', '!']);
+				if !word.is_empty() {
+					*words.entry(word.to_owned()).or_default() += 1;
+				}
+			}
+		}
+		return Self { words };
+	}
+
+	fn frequency(&self, key: &str) -> usize {
+		return self.words.get(key).copied().unwrap_or(0);
+	}
+
+	fn contains(&self, key: &str) -> bool {
+		return self.words.contains_key(key);
+	}
+}
+
+/// Whether a word could have been misread. Numbers on their own are left alone, as
+/// there's nothing to say which one is right.
+fn has_letters(word: &str) -> bool {
+	return word.chars().any(char::is_alphabetic);
+}
+
+/// The word as it's compared, or `None` if it's not a single word
+fn key(word: &str) -> Option<String> {
+	// On a line of its own, a number would be taken for the number of a cue
+	if word.chars().all(|c| c.is_ascii_digit()) {
+		return Some(word.to_owned());
+	}
+	let key = normalize_subtitles(word);
+	let key = key.trim_end_matches(['.', ',', '?', '!']);
+	if key.is_empty() || key.contains(' ') {
+		return None;
+	}
+	return Some(key.to_owned());
+}
+
+/// Corrects OCR errors in subtitles, returning the corrected subtitles and how many
+/// words were changed. Only words that aren't in the dictionary are touched:
+///
+/// 1. Pieces of a word that was split in two are joined, such as "ev ery"
+/// 2. Confused characters are swapped for the ones that make a known word, such as
+///    "lt's" or "rnorning", picking the most common word if several could be meant
+///
+/// Cue numbers and timings are left as they are.
+pub fn correct_ocr(subs: &str, dictionary: &Dictionary) -> (String, usize) {
+	let mut corrected = String::with_capacity(subs.len());
+	let mut corrections = 0;
+	for line in subs.lines() {
+		if line.contains("-->") || line.trim().chars().all(|c| c.is_ascii_digit()) {
+			corrected.push_str(line);
+		} else {
+			let words: Vec<&str> = line.split_whitespace().collect();
+			let mut joined = Vec::<String>::new();
+			let mut idx = 0;
+			while idx < words.len() {
+				if let Some(word) = words
+					.get(idx + 1)
+					.and_then(|next| join_split(words[idx], next, dictionary))
+				{
+					joined.push(word);
+					corrections += 1;
+					idx += 2;
+					continue;
+				}
+				joined.push(words[idx].to_owned());
+				idx += 1;
+			}
+			for (idx, word) in joined.iter().enumerate() {
+				if idx > 0 {
+					corrected.push(' ');
+				}
+				match correct_word(word, dictionary) {
+					Some(word) => {
+						corrected.push_str(&word);
+						corrections += 1;
+					}
+					None => corrected.push_str(word),
+				}
+			}
+		}
+		corrected.push('\n');
+	}
+	return (corrected, corrections);
+}
+
+fn join_split(first: &str, second: &str, dictionary: &Dictionary) -> Option<String> {
+	if !has_letters(first) || !has_letters(second) {
+		return None;
+	}
+	let (Some(first_key), Some(second_key)) = (key(first), key(second)) else {
+		return None;
+	};
+	if dictionary.contains(&first_key) && dictionary.contains(&second_key) {
+		return None;
+	}
+	let joined = format!("{first}{second}");
+	return key(&joined)
+		.is_some_and(|key| dictionary.contains(&key))
+		.then_some(joined);
+}
+
+fn correct_word(word: &str, dictionary: &Dictionary) -> Option<String> {
+	if !has_letters(word) {
+		return None;
+	}
+	let original = key(word)?;
+	if dictionary.contains(&original) {
+		return None;
+	}
+	let mut best: Option<(usize, String)> = None;
+	for (from, to) in CONFUSIONS {
+		let positions: Vec<usize> = word.match_indices(from).map(|(idx, _)| idx).collect();
+		let mut candidates: Vec<String> = positions
+			.iter()
+			.map(|idx| replace_at(word, *idx, from, to))
+			.collect();
+		if positions.len() > 1 {
+			candidates.push(positions.iter().rev().fold(word.to_owned(), |word, idx| {
+				replace_at(&word, *idx, from, to)
+			}));
+		}
+		for candidate in candidates {
+			let Some(candidate_key) = key(&candidate) else {
+				continue;
+			};
+			let frequency = dictionary.frequency(&candidate_key);
+			if frequency > best.as_ref().map_or(0, |(best, _)| *best) {
+				best = Some((frequency, candidate));
+			}
+		}
+	}
+	return best.map(|(_, candidate)| candidate);
+}
+
+/// Replaces the confused characters at a byte offset. A digit read instead of a
+/// letter becomes a capital if the rest of the word is in capitals.
+fn replace_at(word: &str, idx: usize, from: &str, to: &str) -> String {
+	let (before, after) = (&word[..idx], &word[idx + from.len()..]);
+	let shouting = before
+		.chars()
+		.chain(after.chars())
+		.filter(|c| c.is_alphabetic())
+		.all(char::is_uppercase);
+	let to = match from.chars().all(|c| c.is_ascii_digit()) && shouting {
+		true => to.to_uppercase(),
+		false => to.to_owned(),
+	};
+	return format!("{before}{to}{after}");
+}
+
+/// Where the corrected subtitles of a title are written, with `Write`
+pub fn corrected_path(file_path: &Path) -> PathBuf {
+	let mut path = OsString::from(file_path.as_os_str());
+	path.push(".corrected.srt");
+	return PathBuf::from(path);
+}
+
+/// Corrects the OCR errors in the ripped subtitles that were extracted from images,
+/// using the words of the reference subtitles, and prepares them for comparison again
+pub async fn correct_ripped(
+	files: &mut [(PathBuf, Transcript)],
+	references: &[&Transcript],
+	action: OcrCorrection,
+) -> anyhow::Result<()> {
+	if action == OcrCorrection::Off || references.is_empty() {
+		return Ok(());
+	}
+	let dictionary = Dictionary::new(references);
+	let mut corrected_files = 0;
+	let mut total = 0;
+	for (file_path, transcript) in files.iter_mut() {
+		if !has_image_subtitles(&file_path.with_extension("mkv")) {
+			continue;
+		}
+		let srt_file = file_path.with_extension("srt");
+		let subs = fs::read_to_string(&srt_file)
+			.await
+			.with_context(|| format!("Couldn't read {}", srt_file.display()))?;
+		let (corrected, corrections) = correct_ocr(&subs, &dictionary);
+		if corrections == 0 {
+			continue;
+		}
+		*transcript = Transcript::new(&corrected);
+		corrected_files += 1;
+		total += corrections;
+		if action == OcrCorrection::Write {
+			let path = corrected_path(file_path);
+			fs::write(&path, &corrected)
+				.await
+				.with_context(|| format!("Couldn't write {}", path.display()))?;
+		}
+	}
+	if total > 0 {
+		println!("Corrected {total} likely OCR error(s) in {corrected_files} title(s)");
+	}
+	return Ok(());
+}
@@ -6,10 +6,9 @@ use dialoguer::{Input, Password, Select, Confirm};
 use lazy_static::lazy_static;
 use serde::Deserialize;
 use serde_json::json;
-use tmdb_async::Episode;
 use tokio::{sync::RwLock, process::Command, io::AsyncWriteExt, task};
 
-use crate::{interact::{interact, interact_async}, THEME};
+use crate::{interact::{ensure_interactive, interact, interact_async}, language::language, THEME};
 
 lazy_static! {
 	static ref TMDB_API_KEY: String = std::env::var("TMDB_API_KEY")
@@ -35,18 +34,16 @@ pub async fn get_ost_auth() -> anyhow::Result<Arc<str>> {
 			if let Some(ref api_key) = *api_key_writable {
 				return Ok(Arc::clone(api_key));
 			}
-			let login_creds: (String, String) = interact(|| -> anyhow::Result<(String, String)> {
-				let username = Input::with_theme(&*THEME)
-					.with_prompt("OST Username: ")
-					.allow_empty(false)
-					.interact_text()?;
-				let password = Password::with_theme(&*THEME)
-					.with_prompt("Password")
-					.allow_empty_password(false)
-					.interact()?;
-				return Ok((username, password));
-			})
-			.await?;
+			let login_creds: (String, String) = match (
+				std::env::var("OST_USERNAME"),
+				std::env::var("OST_PASSWORD"),
+			) {
+				(Ok(username), Ok(password)) => (username, password),
+				_ => {
+					ensure_interactive("OST_USERNAME and OST_PASSWORD")?;
+					prompt_ost_login().await?
+				}
+			};
 
 			let response: LoginResponse = HTTP_CLIENT
 				.post("https://api.opensubtitles.com/api/v1/login")
@@ -69,6 +66,21 @@ pub async fn get_ost_auth() -> anyhow::Result<Arc<str>> {
 	}
 }
 
+async fn prompt_ost_login() -> anyhow::Result<(String, String)> {
+	return interact(|| -> anyhow::Result<(String, String)> {
+		let username = Input::with_theme(&*THEME)
+			.with_prompt("OST Username: ")
+			.allow_empty(false)
+			.interact_text()?;
+		let password = Password::with_theme(&*THEME)
+			.with_prompt("Password")
+			.allow_empty_password(false)
+			.interact()?;
+		return Ok((username, password));
+	})
+	.await;
+}
+
 #[async_trait]
 pub trait AuthenticateOST {
 	async fn authenticate_ost(self) -> anyhow::Result<reqwest::RequestBuilder>;
@@ -138,10 +150,11 @@ struct SubtitleSummary {
 	file_id: u32,
 }
 
-pub async fn get_subtitles(episode: &Episode, prompt_user: bool) -> anyhow::Result<String> {
+/// Gets subtitles for an episode or movie by its TMDB id
+pub async fn get_subtitles(tmdb_id: u32, prompt_user: bool) -> anyhow::Result<String> {
 	let response: SearchResults = HTTP_CLIENT
 		.get("https://api.opensubtitles.com/api/v1/subtitles")
-		.query(&[("tmdb_id", &episode.id.to_string())])
+		.query(&[("tmdb_id", tmdb_id.to_string().as_str()), ("languages", language().opensubtitles)])
 		.authenticate_ost()
 		.await.context("Couldn't authenticate with OST")?
 		.send()
@@ -170,7 +183,7 @@ pub async fn get_subtitles(episode: &Episode, prompt_user: bool) -> anyhow::Resu
 		})
 		.collect();
 
-	if files.len() == 0 {
+	if files.is_empty() {
 		return Err(anyhow!("No subtitles found for title"));
 	}
 
@@ -183,7 +196,7 @@ pub async fn get_subtitles(episode: &Episode, prompt_user: bool) -> anyhow::Resu
 				let user_selection = task::spawn_blocking(move || {
 					Select::with_theme(&*THEME)
 						.with_prompt("Select a file")
-						.items(&*user_selection_items)
+						.items(&user_selection_items)
 						.default(0)
 						.interact()
 				})
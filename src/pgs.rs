@@ -0,0 +1,522 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Context};
+
+/// How long the last caption of a stream stays up, since nothing clears it
+const LAST_CAPTION_MS: u32 = 5000;
+
+/// Blank space left around the text, which Tesseract reads more reliably
+const PADDING: usize = 10;
+
+/// Blu-ray video is at most 3840x2160, so a composition any larger is from a damaged
+/// stream
+const MAX_VIDEO_SIZE: usize = 4096;
+
+const PALETTE_DEFINITION: u8 = 0x14;
+const OBJECT_DEFINITION: u8 = 0x15;
+const PRESENTATION_COMPOSITION: u8 = 0x16;
+const WINDOW_DEFINITION: u8 = 0x17;
+const END_OF_DISPLAY_SET: u8 = 0x80;
+
+/// A grayscale image of a caption, with dark text on a white background
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bitmap {
+	pub width: usize,
+	pub height: usize,
+	/// One byte per pixel, row by row
+	pub pixels: Vec<u8>,
+}
+
+impl Bitmap {
+	/// Encodes the image as a binary PGM, which Tesseract reads without any help
+	pub fn to_pgm(&self) -> Vec<u8> {
+		let mut pgm = format!("P5\n{} {}\n255\n", self.width, self.height).into_bytes();
+		pgm.extend_from_slice(&self.pixels);
+		return pgm;
+	}
+}
+
+/// A caption from a PGS stream, with its display times in milliseconds
+#[derive(Debug, Clone)]
+pub struct Caption {
+	pub start: u32,
+	pub end: u32,
+	pub image: Bitmap,
+}
+
+/// Luma and alpha of each entry of a palette. Chroma doesn't matter for OCR.
+type Palette = [(u8, u8); 256];
+
+/// An object as it's being received, which can be split over several segments
+struct Object {
+	width: usize,
+	height: usize,
+	data: Vec<u8>,
+	pixels: Option<Vec<u8>>,
+}
+
+/// Where an object is shown, and which part of it
+struct Placement {
+	object_id: u16,
+	x: usize,
+	y: usize,
+	crop: Option<(usize, usize, usize, usize)>,
+}
+
+struct Composition {
+	/// Size of the video the objects are shown on
+	width: usize,
+	height: usize,
+	start: u32,
+	palette_id: u8,
+	placements: Vec<Placement>,
+}
+
+/// Reads big-endian fields out of a segment
+struct Reader<'a> {
+	data: &'a [u8],
+	pos: usize,
+}
+
+impl<'a> Reader<'a> {
+	fn new(data: &'a [u8]) -> Self {
+		return Self { data, pos: 0 };
+	}
+
+	fn bytes(&mut self, len: usize) -> anyhow::Result<&'a [u8]> {
+		let bytes = self
+			.data
+			.get(self.pos..self.pos + len)
+			.ok_or_else(|| anyhow!("Segment ends early, at byte {}", self.pos))?;
+		self.pos += len;
+		return Ok(bytes);
+	}
+
+	fn u8(&mut self) -> anyhow::Result<u8> {
+		return Ok(self.bytes(1)?[0]);
+	}
+
+	fn u16(&mut self) -> anyhow::Result<u16> {
+		let bytes = self.bytes(2)?;
+		return Ok(u16::from_be_bytes([bytes[0], bytes[1]]));
+	}
+
+	fn u24(&mut self) -> anyhow::Result<u32> {
+		let bytes = self.bytes(3)?;
+		return Ok(u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]));
+	}
+
+	fn u32(&mut self) -> anyhow::Result<u32> {
+		let bytes = self.bytes(4)?;
+		return Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]));
+	}
+
+	fn rest(&mut self) -> &'a [u8] {
+		let rest = &self.data[self.pos.min(self.data.len())..];
+		self.pos = self.data.len();
+		return rest;
+	}
+
+	fn is_empty(&self) -> bool {
+		return self.pos >= self.data.len();
+	}
+}
+
+/// Decodes a Blu-ray subtitle stream, as extracted to a `.sup` file by mkvextract,
+/// into the captions it shows.
+///
+/// The stream is a series of display sets, each made of segments: a presentation
+/// composition saying which objects are shown where, window definitions, palette
+/// definitions, object definitions holding run-length encoded bitmaps, and an end
+/// marker. A composition without any objects clears the screen.
+pub fn decode_sup(data: &[u8]) -> anyhow::Result<Vec<Caption>> {
+	let mut stream = Reader::new(data);
+	let mut palettes = HashMap::<u8, Palette>::new();
+	let mut objects = HashMap::<u16, Object>::new();
+	let mut composition: Option<Composition> = None;
+	// Objects can't be larger than the video, which every display set starts with
+	let mut video_size: Option<(usize, usize)> = None;
+	let mut showing: Option<(u32, Bitmap)> = None;
+	let mut captions = Vec::<Caption>::new();
+	while !stream.is_empty() {
+		let offset = stream.pos;
+		if stream.bytes(2)? != b"PG" {
+			return Err(anyhow!("Not a PGS segment at byte {offset}"));
+		}
+		// Presentation timestamps are in 90kHz ticks
+		let pts = stream.u32()? / 90;
+		let _dts = stream.u32()?;
+		let kind = stream.u8()?;
+		let len = stream.u16()? as usize;
+		let mut segment = Reader::new(stream.bytes(len)?);
+		match kind {
+			PRESENTATION_COMPOSITION => {
+				let (parsed, epoch_start) = parse_composition(&mut segment, pts)
+					.with_context(|| format!("Invalid composition at byte {offset}"))?;
+				// An epoch start means everything from before is gone
+				if epoch_start {
+					palettes.clear();
+					objects.clear();
+				}
+				video_size = Some((parsed.width, parsed.height));
+				composition = Some(parsed);
+			}
+			PALETTE_DEFINITION => {
+				let palette_id = segment.u8()?;
+				let _version = segment.u8()?;
+				let palette = palettes.entry(palette_id).or_insert([(0, 0); 256]);
+				while !segment.is_empty() {
+					let entry = segment.u8()?;
+					let (luma, _cr, _cb, alpha) =
+						(segment.u8()?, segment.u8()?, segment.u8()?, segment.u8()?);
+					palette[entry as usize] = (luma, alpha);
+				}
+			}
+			OBJECT_DEFINITION => {
+				let object_id = segment.u16()?;
+				let _version = segment.u8()?;
+				let sequence = segment.u8()?;
+				if sequence & 0x80 != 0 {
+					let _len = segment.u24()?;
+					let width = segment.u16()? as usize;
+					let height = segment.u16()? as usize;
+					if video_size.is_none_or(|(video_width, video_height)| {
+						width > video_width || height > video_height
+					}) {
+						return Err(anyhow!(
+							"Object {object_id} at byte {offset} is larger than the video"
+						));
+					}
+					objects.insert(
+						object_id,
+						Object {
+							width,
+							height,
+							data: Vec::new(),
+							pixels: None,
+						},
+					);
+				}
+				let object = objects.get_mut(&object_id).ok_or_else(|| {
+					anyhow!("Object {object_id} continues at byte {offset} without a start")
+				})?;
+				object.data.extend_from_slice(segment.rest());
+				if sequence & 0x40 != 0 {
+					object.pixels = Some(decode_rle(&object.data, object.width, object.height));
+					object.data = Vec::new();
+				}
+			}
+			WINDOW_DEFINITION => {}
+			END_OF_DISPLAY_SET => {
+				let Some(composition) = composition.take() else {
+					continue;
+				};
+				let palette = palettes
+					.get(&composition.palette_id)
+					.copied()
+					.unwrap_or([(0, 0); 256]);
+				let image = render(&composition.placements, &objects, &palette);
+				// Palette updates that only fade the same caption in or out don't start
+				// a new one
+				if let (Some((_, current)), Some(image)) = (&showing, &image) {
+					if current == image {
+						continue;
+					}
+				}
+				if let Some((start, image)) = showing.take() {
+					captions.push(Caption {
+						start,
+						end: composition.start,
+						image,
+					});
+				}
+				showing = image.map(|image| (composition.start, image));
+			}
+			_ => {}
+		}
+	}
+	if let Some((start, image)) = showing {
+		captions.push(Caption {
+			start,
+			end: start + LAST_CAPTION_MS,
+			image,
+		});
+	}
+	return Ok(captions);
+}
+
+/// Reads a presentation composition segment, returning it and whether it starts a
+/// new epoch
+fn parse_composition(segment: &mut Reader, start: u32) -> anyhow::Result<(Composition, bool)> {
+	let width = segment.u16()? as usize;
+	let height = segment.u16()? as usize;
+	if width > MAX_VIDEO_SIZE || height > MAX_VIDEO_SIZE {
+		return Err(anyhow!("The video can't be {width}x{height}"));
+	}
+	let _frame_rate = segment.u8()?;
+	let _number = segment.u16()?;
+	let state = segment.u8()?;
+	let _palette_update = segment.u8()?;
+	let palette_id = segment.u8()?;
+	let count = segment.u8()?;
+	let mut placements = Vec::<Placement>::new();
+	for _ in 0..count {
+		let object_id = segment.u16()?;
+		let _window_id = segment.u8()?;
+		let cropped = segment.u8()? & 0x40 != 0;
+		let x = segment.u16()? as usize;
+		let y = segment.u16()? as usize;
+		if x >= width || y >= height {
+			return Err(anyhow!("Object {object_id} is placed outside of the video"));
+		}
+		let crop = match cropped {
+			true => Some((
+				segment.u16()? as usize,
+				segment.u16()? as usize,
+				segment.u16()? as usize,
+				segment.u16()? as usize,
+			)),
+			false => None,
+		};
+		placements.push(Placement {
+			object_id,
+			x,
+			y,
+			crop,
+		});
+	}
+	let composition = Composition {
+		width,
+		height,
+		start,
+		palette_id,
+		placements,
+	};
+	return Ok((composition, state & 0x80 != 0));
+}
+
+/// Decodes the run-length encoded palette indexes of an object. Each line ends with
+/// its own marker, and lines that come out too short are padded and those that would
+/// come out too long are cut off, so a damaged object still has the right size.
+///
+/// - `CC`: one pixel of color CC, which isn't 0
+/// - `00 00`: end of the line
+/// - `00 0L`, `00 4L LL`: L pixels of color 0, with L in 6 or 14 bits
+/// - `00 8L CC`, `00 CL LL CC`: L pixels of color CC, with L in 6 or 14 bits
+fn decode_rle(data: &[u8], width: usize, height: usize) -> Vec<u8> {
+	let mut pixels = Vec::<u8>::with_capacity(width * height);
+	let mut line = Vec::<u8>::with_capacity(width);
+	let mut bytes = data.iter().copied();
+	while pixels.len() < width * height {
+		let Some(byte) = bytes.next() else {
+			break;
+		};
+		if byte != 0 {
+			if line.len() < width {
+				line.push(byte);
+			}
+			continue;
+		}
+		let Some(flags) = bytes.next() else {
+			break;
+		};
+		if flags == 0 {
+			line.resize(width, 0);
+			pixels.append(&mut line);
+			continue;
+		}
+		let mut len = (flags & 0x3F) as usize;
+		if flags & 0x40 != 0 {
+			len = len << 8 | bytes.next().unwrap_or(0) as usize;
+		}
+		let color = match flags & 0x80 != 0 {
+			true => bytes.next().unwrap_or(0),
+			false => 0,
+		};
+		line.resize((line.len() + len).min(width), color);
+	}
+	if !line.is_empty() {
+		line.resize(width, 0);
+		pixels.append(&mut line);
+	}
+	pixels.resize(width * height, 0);
+	return pixels;
+}
+
+/// The part of an object that's shown, and where
+struct Piece<'a> {
+	object: &'a Object,
+	pixels: &'a [u8],
+	x: usize,
+	y: usize,
+	width: usize,
+	height: usize,
+	crop_x: usize,
+	crop_y: usize,
+}
+
+/// Draws the objects of a composition onto one image, cropped to the area they cover.
+/// Returns `None` if nothing is shown.
+fn render(
+	placements: &[Placement],
+	objects: &HashMap<u16, Object>,
+	palette: &Palette,
+) -> Option<Bitmap> {
+	let mut pieces = Vec::<Piece>::new();
+	for placement in placements {
+		let Some(object) = objects.get(&placement.object_id) else {
+			continue;
+		};
+		let Some(ref pixels) = object.pixels else {
+			continue;
+		};
+		let (crop_x, crop_y, width, height) =
+			placement
+				.crop
+				.unwrap_or((0, 0, object.width, object.height));
+		let piece = Piece {
+			object,
+			pixels,
+			x: placement.x,
+			y: placement.y,
+			width: width.min(object.width.saturating_sub(crop_x)),
+			height: height.min(object.height.saturating_sub(crop_y)),
+			crop_x,
+			crop_y,
+		};
+		if piece.width > 0 && piece.height > 0 {
+			pieces.push(piece);
+		}
+	}
+	let left = pieces.iter().map(|piece| piece.x).min()?;
+	let top = pieces.iter().map(|piece| piece.y).min()?;
+	let right = pieces.iter().map(|piece| piece.x + piece.width).max()?;
+	let bottom = pieces.iter().map(|piece| piece.y + piece.height).max()?;
+	let width = right - left + PADDING * 2;
+	let height = bottom - top + PADDING * 2;
+	let mut canvas = vec![255u8; width * height];
+	for piece in pieces {
+		for row in 0..piece.height {
+			let source = (piece.crop_y + row) * piece.object.width + piece.crop_x;
+			let target = (piece.y - top + PADDING + row) * width + piece.x - left + PADDING;
+			for column in 0..piece.width {
+				let (luma, alpha) = palette[piece.pixels[source + column] as usize];
+				// Luma uses the video range of 16 to 235. Bright, opaque pixels are the
+				// text, and dark outlines and transparent pixels are background.
+				let luma = (luma.saturating_sub(16) as u32 * 255 / 219).min(255);
+				let ink = (luma * alpha as u32 / 255) as u8;
+				let pixel = &mut canvas[target + column];
+				*pixel = (*pixel).min(255 - ink);
+			}
+		}
+	}
+	if canvas.iter().all(|pixel| *pixel == 255) {
+		return None;
+	}
+	return Some(Bitmap {
+		width,
+		height,
+		pixels: canvas,
+	});
+}
+
+#[cfg(test)]
+mod tests {
+	// Decodes small PGS streams put together by hand, covering each kind of run in the
+	// bitmaps and a composition that clears the screen.
+
+	use super::*;
+
+	/// A segment with its header, shown at `pts_ms`
+	fn segment(pts_ms: u32, kind: u8, payload: &[u8]) -> Vec<u8> {
+		let mut segment = b"PG".to_vec();
+		segment.extend((pts_ms * 90).to_be_bytes());
+		segment.extend(0u32.to_be_bytes());
+		segment.push(kind);
+		segment.extend((payload.len() as u16).to_be_bytes());
+		segment.extend(payload);
+		return segment;
+	}
+
+	/// A composition showing object 0 at (100, 200), or nothing
+	fn composition(pts_ms: u32, shown: bool) -> Vec<u8> {
+		let mut payload = vec![0x07, 0x80, 0x04, 0x38, 0x10, 0x00, 0x01];
+		payload.push(if shown { 0x80 } else { 0x00 });
+		payload.extend([0x00, 0x00, shown as u8]);
+		if shown {
+			payload.extend([0x00, 0x00, 0x00, 0x00, 0x00, 0x64, 0x00, 0xC8]);
+		}
+		return segment(pts_ms, 0x16, &payload);
+	}
+
+	#[test]
+	fn decodes_a_caption_until_the_screen_is_cleared() {
+		// Palette 0, with entry 1 as opaque white
+		let palette = [0x00, 0x00, 0x01, 0xEB, 0x80, 0x80, 0xFF];
+		// An 8x2 object: one pixel, a short run of color 0 and a long run of color 1, then
+		// a long run of color 0
+		let mut object = vec![
+			0x00, 0x00, 0x00, 0xC0, 0x00, 0x00, 0x12, 0x00, 0x08, 0x00, 0x02,
+		];
+		object.extend([0x01, 0x00, 0x02, 0x00, 0xC0, 0x05, 0x01, 0x00, 0x00]);
+		object.extend([0x00, 0x40, 0x08, 0x00, 0x00]);
+
+		let mut stream = composition(1000, true);
+		stream.extend(segment(1000, 0x14, &palette));
+		stream.extend(segment(1000, 0x15, &object));
+		stream.extend(segment(1000, 0x80, &[]));
+		stream.extend(composition(2500, false));
+		stream.extend(segment(2500, 0x80, &[]));
+
+		let captions = decode_sup(&stream).unwrap();
+		assert_eq!(captions.len(), 1);
+		let caption = &captions[0];
+		assert_eq!((caption.start, caption.end), (1000, 2500));
+
+		// The text is drawn dark on white, with 10 pixels of padding around it
+		let image = &caption.image;
+		assert_eq!((image.width, image.height), (28, 22));
+		let row: Vec<u8> = image.pixels[10 * 28 + 10..10 * 28 + 18].to_vec();
+		assert_eq!(row, [0, 255, 255, 0, 0, 0, 0, 0]);
+		assert!(image.pixels[11 * 28 + 10..11 * 28 + 18]
+			.iter()
+			.all(|pixel| *pixel == 255));
+	}
+
+	#[test]
+	fn rejects_data_that_is_not_pgs() {
+		assert!(decode_sup(b"1\n00:00:01,000 --> 00:00:02,000\nHello\n").is_err());
+	}
+
+	#[test]
+	fn rejects_objects_larger_than_the_video() {
+		// A 65535x65535 object, on 1920x1080 video
+		let object = [
+			0x00, 0x00, 0x00, 0xC0, 0x00, 0x00, 0x04, 0xFF, 0xFF, 0xFF, 0xFF,
+		];
+		let mut stream = composition(1000, true);
+		stream.extend(segment(1000, 0x15, &object));
+		assert!(decode_sup(&stream).is_err());
+	}
+
+	#[test]
+	fn cuts_off_lines_without_an_end() {
+		let palette = [0x00, 0x00, 0x01, 0xEB, 0x80, 0x80, 0xFF];
+		// A 4x1 object with a run of 1000 pixels of color 1, and no end of the line
+		let object = [
+			0x00, 0x00, 0x00, 0xC0, 0x00, 0x00, 0x08, 0x00, 0x04, 0x00, 0x01, 0x00, 0xC3, 0xE8,
+			0x01,
+		];
+		let mut stream = composition(1000, true);
+		stream.extend(segment(1000, 0x14, &palette));
+		stream.extend(segment(1000, 0x15, &object));
+		stream.extend(segment(1000, 0x80, &[]));
+
+		let captions = decode_sup(&stream).unwrap();
+		let image = &captions[0].image;
+		assert_eq!((image.width, image.height), (24, 21));
+		assert!(image.pixels[10 * 24 + 10..10 * 24 + 14]
+			.iter()
+			.all(|pixel| *pixel == 0));
+	}
+}
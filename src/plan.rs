@@ -0,0 +1,455 @@
+use std::{
+	collections::HashSet,
+	io::ErrorKind,
+	path::{Path, PathBuf},
+	time::Duration,
+};
+
+use anyhow::{anyhow, Context};
+use dialoguer::Confirm;
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+
+use crate::{
+	box_set::{BoxSet, BoxSetKey},
+	extras::ExtraKind,
+	interact::{interact, is_unattended},
+	journal::{Action, Journal},
+	play_all::split_title,
+	THEME,
+};
+
+/// Everything `tag` intends to do to the files on a disc. Plans can be written out,
+/// reviewed or edited by hand, and then carried out later with `apply`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Plan {
+	pub entries: Vec<PlanEntry>,
+	/// Files that will be left where they are
+	#[serde(default)]
+	pub unmatched: Vec<PathBuf>,
+	/// Files that will be moved to the trash
+	#[serde(default)]
+	pub deletions: Vec<Deletion>,
+	/// Play-all titles that will be cut into a file per episode, before the files are
+	/// renamed
+	#[serde(default)]
+	pub splits: Vec<Split>,
+	/// Box sets that are updated with the episodes once they're renamed
+	#[serde(default)]
+	pub box_sets: Vec<BoxSetKey>,
+}
+
+/// A single file to be renamed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlanEntry {
+	pub source: PathBuf,
+	pub target: PathBuf,
+	/// TMDB ids of the episodes, or the movie, contained in the file
+	pub episode_ids: Vec<u32>,
+	/// Files that only existed to identify `source`, and are trashed once it's renamed
+	#[serde(default)]
+	pub sidecars: Vec<PathBuf>,
+	/// How alike the file's subtitles were to the episode's, from 0 to 1
+	pub similarity: f64,
+	/// How far ahead of the runner-up the match was, from 0 to 1
+	pub confidence: f64,
+	/// Whether an existing file at `target` may be replaced. It's moved to the trash.
+	#[serde(default)]
+	pub replace: bool,
+	/// Set when the file didn't match anything, and is being sorted as an extra
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub extra: Option<ExtraKind>,
+}
+
+/// A file to be moved to the trash, such as a copy of another title
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Deletion {
+	pub path: PathBuf,
+	/// Files that only existed to identify `path`, and go with it
+	#[serde(default)]
+	pub sidecars: Vec<PathBuf>,
+}
+
+/// A play-all title to be cut up with mkvmerge. The parts are renamed by entries of
+/// their own.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Split {
+	pub source: PathBuf,
+	pub parts: Vec<SplitPart>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SplitPart {
+	/// The file mkvmerge will create
+	pub path: PathBuf,
+	/// Where the part begins within the title
+	pub start: Duration,
+	/// Where the part ends, or `None` for the end of the title
+	#[serde(default)]
+	pub end: Option<Duration>,
+}
+
+impl Plan {
+	pub async fn read(path: &Path) -> anyhow::Result<Self> {
+		let contents = fs::read_to_string(path)
+			.await
+			.with_context(|| format!("Couldn't read plan {}", path.display()))?;
+		return serde_json::from_str(&contents)
+			.with_context(|| format!("{} is not a valid plan", path.display()));
+	}
+
+	/// Writes the plan with every path made absolute, so it can be applied from any
+	/// directory
+	pub async fn write(&self, path: &Path) -> anyhow::Result<()> {
+		let mut plan = self.clone();
+		for entry in &mut plan.entries {
+			entry.source = absolute(&entry.source)?;
+			entry.target = absolute(&entry.target)?;
+			for sidecar in &mut entry.sidecars {
+				*sidecar = absolute(sidecar)?;
+			}
+		}
+		for path in &mut plan.unmatched {
+			*path = absolute(path)?;
+		}
+		for deletion in &mut plan.deletions {
+			deletion.path = absolute(&deletion.path)?;
+			for sidecar in &mut deletion.sidecars {
+				*sidecar = absolute(sidecar)?;
+			}
+		}
+		for split in &mut plan.splits {
+			split.source = absolute(&split.source)?;
+			for part in &mut split.parts {
+				part.path = absolute(&part.path)?;
+			}
+		}
+		fs::write(path, serde_json::to_string_pretty(&plan)?)
+			.await
+			.with_context(|| format!("Couldn't write plan {}", path.display()))?;
+		return Ok(());
+	}
+}
+
+fn absolute(path: &Path) -> anyhow::Result<PathBuf> {
+	return std::path::absolute(path)
+		.with_context(|| format!("Couldn't resolve {}", path.display()));
+}
+
+impl PlanEntry {
+	/// Moves the file into place and cleans up its sidecars, recording everything in
+	/// the journal
+	pub async fn apply(&self, journal: &Journal) -> anyhow::Result<()> {
+		let mut replaced = None;
+		if fs::try_exists(&self.target).await? {
+			if !self.replace {
+				return Err(anyhow!("Refusing to overwrite {}", self.target.display()));
+			}
+			replaced = Some(journal.trash(&self.target).await?);
+		}
+
+		// Remember which folders didn't exist yet, so undo can clean them up
+		let mut created = Vec::<PathBuf>::new();
+		for folder in self.target.ancestors().skip(1) {
+			if folder.as_os_str().is_empty() || fs::try_exists(folder).await? {
+				break;
+			}
+			created.push(folder.to_owned());
+		}
+		if let Some(folder) = created.first() {
+			fs::create_dir_all(folder)
+				.await
+				.with_context(|| format!("Couldn't create {}", folder.display()))?;
+		}
+
+		if let Err(err) = move_file(&self.source, &self.target).await {
+			for folder in &created {
+				let _ = fs::remove_dir(folder).await;
+			}
+			if let Some(replaced) = replaced {
+				move_file(&replaced.trash, &self.target)
+					.await
+					.with_context(|| format!("Couldn't restore {}", self.target.display()))?;
+			}
+			return Err(err);
+		}
+
+		// The rename is recorded even if a sidecar can't be trashed, so it can still be
+		// undone
+		let mut trashed = Vec::new();
+		let mut result = Ok(());
+		for sidecar in &self.sidecars {
+			match journal.trash(sidecar).await {
+				Ok(file) => trashed.push(file),
+				Err(err) => {
+					result = Err(err);
+					break;
+				}
+			}
+		}
+		journal
+			.record(Action::Rename {
+				source: self.source.clone(),
+				target: self.target.clone(),
+				trashed,
+				replaced,
+				created,
+			})
+			.await?;
+		return result;
+	}
+}
+
+impl Deletion {
+	/// Moves the file and its sidecars to the trash, recording it in the journal
+	pub async fn apply(&self, journal: &Journal) -> anyhow::Result<()> {
+		let mut trashed = Vec::new();
+		let mut result = Ok(());
+		for path in [&self.path].into_iter().chain(&self.sidecars) {
+			match journal.trash(path).await {
+				Ok(file) => trashed.push(file),
+				Err(err) => {
+					result = Err(err);
+					break;
+				}
+			}
+		}
+		if !trashed.is_empty() {
+			journal.record(Action::Delete { trashed }).await?;
+		}
+		return result;
+	}
+}
+
+impl Split {
+	/// Whether `path` is one of the parts this will create
+	pub fn creates(&self, path: &Path) -> bool {
+		return self.parts.iter().any(|part| part.path == path);
+	}
+
+	/// Cuts the title into its parts, recording them in the journal so undo removes
+	/// them
+	pub async fn apply(&self, journal: &Journal) -> anyhow::Result<()> {
+		for part in &self.parts {
+			if fs::try_exists(&part.path).await? {
+				return Err(anyhow!("Refusing to overwrite {}", part.path.display()));
+			}
+		}
+		let ranges: Vec<(Duration, Option<Duration>)> = self
+			.parts
+			.iter()
+			.map(|part| (part.start, part.end))
+			.collect();
+		let created = match split_title(&self.source, &ranges).await {
+			Ok(created) => created,
+			Err(err) => {
+				// Whatever mkvmerge got through is incomplete
+				for part in &self.parts {
+					let _ = fs::remove_file(&part.path).await;
+				}
+				return Err(err);
+			}
+		};
+		journal
+			.record(Action::Split {
+				source: self.source.clone(),
+				created,
+			})
+			.await?;
+		return Ok(());
+	}
+}
+
+/// Moves a file, falling back to copying it when `target` is on another filesystem
+pub async fn move_file(source: &Path, target: &Path) -> anyhow::Result<()> {
+	match fs::rename(source, target).await {
+		Ok(()) => return Ok(()),
+		Err(err) if err.kind() == ErrorKind::CrossesDevices => {}
+		Err(err) => {
+			return Err(err).with_context(|| format!("Couldn't move {}", source.display()));
+		}
+	}
+
+	// Copy under a temporary name first, so an interrupted copy is never mistaken for
+	// a finished one
+	let mut partial = target.as_os_str().to_owned();
+	partial.push(".partial");
+	let partial = PathBuf::from(partial);
+	if let Err(err) = fs::copy(source, &partial).await {
+		let _ = fs::remove_file(&partial).await;
+		return Err(err).with_context(|| {
+			format!("Couldn't copy {} to {}", source.display(), target.display())
+		});
+	}
+	fs::rename(&partial, target)
+		.await
+		.with_context(|| format!("Couldn't move {}", source.display()))?;
+	fs::remove_file(source)
+		.await
+		.with_context(|| format!("Couldn't remove {}", source.display()))?;
+	return Ok(());
+}
+
+impl Plan {
+	/// Asks about each entry before applying it. Entries at or above `auto_accept`
+	/// are applied without asking, and when running unattended, entries below it are
+	/// skipped. Entries are added to `applied` as they're applied, so they're known
+	/// even if a later one fails.
+	pub async fn confirm_and_apply<'a>(
+		&'a self,
+		auto_accept: Option<f64>,
+		applied: &mut Vec<&'a PlanEntry>,
+	) -> anyhow::Result<()> {
+		let journal = Journal::start();
+		let mut accepted = Vec::<&PlanEntry>::new();
+		for entry in &self.entries {
+			if !entry.replace && fs::try_exists(&entry.target).await? {
+				println!(
+					"Skipped {:?} => {:?} (already exists, use --replace to overwrite it)",
+					&entry.source, &entry.target
+				);
+				continue;
+			}
+			let rename = match auto_accept {
+				Some(threshold) if entry.confidence >= threshold => {
+					println!(
+						"Accepted {:?} => {:?} (confidence {:.2})",
+						&entry.source, &entry.target, entry.confidence
+					);
+					true
+				}
+				Some(_) if is_unattended() => {
+					println!(
+						"Skipped {:?} => {:?} (confidence {:.2})",
+						&entry.source, &entry.target, entry.confidence
+					);
+					false
+				}
+				_ if is_unattended() => true,
+				_ => {
+					let prompt = format!("Rename {:?} to {:?}?", &entry.source, &entry.target);
+					interact(|| Confirm::with_theme(&*THEME).with_prompt(prompt).interact()).await?
+				}
+			};
+			if rename {
+				accepted.push(entry);
+			}
+		}
+
+		// Titles are only cut up if one of their parts is wanted, and before anything
+		// is renamed in case the title itself is
+		let mut split = Vec::<&Split>::new();
+		for candidate in &self.splits {
+			if accepted
+				.iter()
+				.any(|entry| candidate.creates(&entry.source))
+			{
+				println!("Splitting {:?}", &candidate.source);
+				candidate.apply(&journal).await?;
+				split.push(candidate);
+			}
+		}
+		for entry in accepted {
+			entry.apply(&journal).await?;
+			applied.push(entry);
+		}
+		// Parts that weren't wanted are only copies, so they're removed again
+		for part in split.iter().flat_map(|split| &split.parts) {
+			if !applied.iter().any(|entry| entry.source == part.path) {
+				let _ = fs::remove_file(&part.path).await;
+			}
+		}
+		for deletion in &self.deletions {
+			let delete = is_unattended() || {
+				let prompt = format!("Delete {:?}?", &deletion.path);
+				interact(|| Confirm::with_theme(&*THEME).with_prompt(prompt).interact()).await?
+			};
+			if delete {
+				println!(
+					"Deleted {:?} (it can be restored with undo)",
+					&deletion.path
+				);
+				deletion.apply(&journal).await?;
+			}
+		}
+		return Ok(());
+	}
+
+	/// Remembers the episodes in the entries that were applied in the plan's box sets
+	pub async fn update_box_sets(&self, applied: &[&PlanEntry]) -> anyhow::Result<()> {
+		for key in &self.box_sets {
+			let mut box_set = BoxSet::load(key).await?;
+			box_set.record(applied)?;
+			box_set.save().await?;
+			println!(
+				"{}",
+				box_set.describe_progress(|episode_id| box_set.position(episode_id))
+			);
+		}
+		return Ok(());
+	}
+}
+
+/// Carries out a plan that was written by `tag --plan`
+pub async fn apply_plan(path: &Path) -> anyhow::Result<()> {
+	let plan = Plan::read(path).await?;
+
+	// Check everything up front so a bad plan doesn't stop halfway through
+	let mut targets = HashSet::<&Path>::new();
+	for entry in &plan.entries {
+		if !targets.insert(&entry.target) {
+			return Err(anyhow!(
+				"More than one file would be renamed to {}",
+				entry.target.display()
+			));
+		}
+		let split = plan.splits.iter().any(|split| split.creates(&entry.source));
+		if !split && !fs::try_exists(&entry.source).await? {
+			return Err(anyhow!("{} doesn't exist", entry.source.display()));
+		}
+		if !entry.replace && fs::try_exists(&entry.target).await? {
+			return Err(anyhow!("{} already exists", entry.target.display()));
+		}
+	}
+
+	for deletion in &plan.deletions {
+		if !fs::try_exists(&deletion.path).await? {
+			return Err(anyhow!("{} doesn't exist", deletion.path.display()));
+		}
+	}
+	for split in &plan.splits {
+		if !fs::try_exists(&split.source).await? {
+			return Err(anyhow!("{} doesn't exist", split.source.display()));
+		}
+		for part in &split.parts {
+			if fs::try_exists(&part.path).await? {
+				return Err(anyhow!("{} already exists", part.path.display()));
+			}
+		}
+	}
+
+	let mut applied = Vec::<&PlanEntry>::new();
+	let result = apply_all(&plan, &mut applied).await;
+	if !plan.box_sets.is_empty() {
+		plan.update_box_sets(&applied).await?;
+	}
+	return result;
+}
+
+async fn apply_all<'a>(plan: &'a Plan, applied: &mut Vec<&'a PlanEntry>) -> anyhow::Result<()> {
+	let journal = Journal::start();
+	for split in &plan.splits {
+		println!("Splitting {:?}", &split.source);
+		split.apply(&journal).await?;
+	}
+	for entry in &plan.entries {
+		println!("{:?} => {:?}", &entry.source, &entry.target);
+		entry.apply(&journal).await?;
+		applied.push(entry);
+	}
+	for deletion in &plan.deletions {
+		println!("Deleting {:?}", &deletion.path);
+		deletion.apply(&journal).await?;
+	}
+	return Ok(());
+}
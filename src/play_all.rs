@@ -0,0 +1,307 @@
+use std::{
+	collections::HashSet,
+	path::{Path, PathBuf},
+	time::Duration,
+};
+
+use anyhow::{anyhow, Context};
+use tokio::process::Command;
+
+use crate::{
+	alignment::{align_cues, Cue},
+	autotagger::Transcript,
+	edit_distance::tokenize,
+	normalize::normalize_subtitles,
+};
+
+/// Without chapter marks, the subtitles are cut into windows of this length instead
+const WINDOW: Duration = Duration::from_secs(120);
+
+/// Segments with fewer words than this, such as opening credits, are left for their
+/// neighbours to claim
+const MIN_SEGMENT_WORDS: usize = 20;
+
+/// Fraction of a segment's word pairs that have to appear in an episode for the
+/// segment to count as part of it
+const MIN_CONTAINMENT: f64 = 0.4;
+
+/// Number of cues that need to line up before the subtitle timing is trusted to say
+/// where an episode starts
+const MIN_ALIGNED_CUES: usize = 10;
+
+/// How far a chapter mark may be from where the subtitles say an episode starts and
+/// still be taken as its start
+const CHAPTER_SNAP: Duration = Duration::from_secs(60);
+
+/// An episode found inside of a play-all title
+#[derive(Debug, Clone)]
+pub struct EpisodeSpan {
+	pub episode_id: u32,
+	/// Where the episode begins within the title
+	pub start: Duration,
+	/// Whether `start` is a chapter mark, rather than estimated from the subtitles
+	pub chapter: bool,
+	/// Average fraction of the episode's segments found in its subtitles, from 0 to 1
+	pub containment: f64,
+}
+impl EpisodeSpan {
+	/// How sure we are the segments belong to this episode, measured against the point
+	/// where they wouldn't have been claimed at all
+	pub fn confidence(&self) -> f64 {
+		if self.containment <= 0.0 {
+			return 0.0;
+		}
+		return ((self.containment - MIN_CONTAINMENT) / self.containment).clamp(0.0, 1.0);
+	}
+}
+
+/// Consecutive segments that were claimed by the same episode
+struct Run {
+	reference: usize,
+	first: usize,
+	last: usize,
+	/// Total containment of the claimed segments
+	containment: f64,
+	segments: usize,
+}
+
+/// Looks for several episodes played back to back in a title, returning where each
+/// of them begins. Returns `None` unless at least two episodes were found in order.
+///
+/// The title is cut into segments at its chapter marks, and each segment is claimed
+/// by whichever episode contains most of its dialogue. Subtitle timing then places
+/// the start of each episode, which is snapped to a chapter mark when one is close.
+pub fn find_episodes(
+	ripped: &Transcript,
+	chapters: &[Duration],
+	references: &[(u32, &Transcript)],
+) -> Option<Vec<EpisodeSpan>> {
+	let cues = &ripped.cues;
+	let last_cue = cues.last()?;
+	let mut boundaries: Vec<Duration> = if chapters.len() >= 2 {
+		chapters.to_vec()
+	} else {
+		(0..)
+			.map(|idx| WINDOW * idx)
+			.take_while(|start| start.as_millis() <= last_cue.end as u128)
+			.collect()
+	};
+	boundaries.push(Duration::ZERO);
+	boundaries.sort_unstable();
+	boundaries.dedup();
+
+	let reference_pairs: Vec<HashSet<(u32, u32)>> = references
+		.iter()
+		.map(|(_, reference)| word_pairs(&reference.tokens))
+		.collect();
+	let mut labels = Vec::<Option<(usize, f64)>>::new();
+	for (idx, start) in boundaries.iter().enumerate() {
+		let end = boundaries.get(idx + 1).copied().unwrap_or(Duration::MAX);
+		let text: Vec<&str> = cues_between(cues, *start, end)
+			.map(|cue| cue.text.as_str())
+			.collect();
+		let tokens = tokenize(&normalize_subtitles(&text.join("\n")));
+		if tokens.len() < MIN_SEGMENT_WORDS {
+			labels.push(None);
+			continue;
+		}
+		let pairs = word_pairs(&tokens);
+		let best = reference_pairs
+			.iter()
+			.enumerate()
+			.map(|(reference, reference_pairs)| {
+				let found = pairs
+					.iter()
+					.filter(|pair| reference_pairs.contains(pair))
+					.count();
+				(reference, found as f64 / pairs.len() as f64)
+			})
+			.max_by(|a, b| a.1.total_cmp(&b.1));
+		labels.push(best.filter(|(_, containment)| *containment >= MIN_CONTAINMENT));
+	}
+
+	let mut runs = Vec::<Run>::new();
+	for (idx, label) in labels.iter().enumerate() {
+		let Some((reference, containment)) = *label else {
+			continue;
+		};
+		if let Some(run) = runs.last_mut().filter(|run| run.reference == reference) {
+			run.last = idx;
+			run.containment += containment;
+			run.segments += 1;
+			continue;
+		}
+		// An episode showing up again once another has started is a recap, not a second
+		// copy
+		if runs.iter().any(|run| run.reference == reference) {
+			continue;
+		}
+		runs.push(Run {
+			reference,
+			first: idx,
+			last: idx,
+			containment,
+			segments: 1,
+		});
+	}
+	if runs.len() < 2 {
+		return None;
+	}
+
+	let mut spans = Vec::<EpisodeSpan>::new();
+	for (run_idx, run) in runs.iter().enumerate() {
+		// Anything between the dialogue of one episode and the next could be the end of
+		// the first or the beginning of the second
+		let earliest = match run_idx {
+			0 => Duration::ZERO,
+			_ => boundaries[runs[run_idx - 1].last + 1],
+		};
+		let end = boundaries
+			.get(run.last + 1)
+			.copied()
+			.unwrap_or(Duration::MAX);
+		let (episode_id, reference) = references[run.reference];
+		let (start, chapter) = match estimate_start(reference, cues, earliest, end) {
+			Some(estimate) => chapters
+				.iter()
+				.filter(|chapter| chapter.abs_diff(estimate) <= CHAPTER_SNAP)
+				.min_by_key(|chapter| chapter.abs_diff(estimate))
+				.map_or((estimate, false), |chapter| (*chapter, true)),
+			None => (boundaries[run.first], chapters.len() >= 2),
+		};
+		spans.push(EpisodeSpan {
+			episode_id,
+			start,
+			chapter,
+			containment: run.containment / run.segments as f64,
+		});
+	}
+	return Some(spans);
+}
+
+/// Works out where an episode's own timeline starts within the title, by lining up
+/// its subtitles against the ones between `earliest` and `end`
+fn estimate_start(
+	reference: &Transcript,
+	cues: &[Cue],
+	earliest: Duration,
+	end: Duration,
+) -> Option<Duration> {
+	let offset = earliest.as_millis() as u32;
+	let shifted: Vec<Cue> = cues_between(cues, earliest, end)
+		.map(|cue| Cue {
+			start: cue.start - offset,
+			end: cue.end.saturating_sub(offset),
+			text: String::new(),
+		})
+		.collect();
+	let alignment = align_cues(&reference.cues, &shifted);
+	if alignment.matched < MIN_ALIGNED_CUES {
+		return None;
+	}
+	// The reference starts at 0, which lands here in the title
+	let start_ms = -alignment.offset_ms / alignment.drift;
+	return Some(earliest + Duration::from_millis(start_ms.max(0.0) as u64));
+}
+
+fn cues_between(cues: &[Cue], start: Duration, end: Duration) -> impl Iterator<Item = &Cue> {
+	return cues.iter().filter(move |cue| {
+		let cue_start = Duration::from_millis(cue.start as u64);
+		cue_start >= start && cue_start < end
+	});
+}
+
+fn word_pairs(tokens: &[u32]) -> HashSet<(u32, u32)> {
+	return tokens.windows(2).map(|pair| (pair[0], pair[1])).collect();
+}
+
+/// Start times of the chapters in an mkv file, if it has any
+pub fn get_chapters(file: &Path) -> Vec<Duration> {
+	let Ok(mkv) = matroska::open(file) else {
+		return Vec::new();
+	};
+	let Some(edition) = mkv
+		.chapters
+		.iter()
+		.find(|edition| edition.default)
+		.or(mkv.chapters.first())
+	else {
+		return Vec::new();
+	};
+	let mut chapters: Vec<Duration> = edition
+		.chapters
+		.iter()
+		.filter(|chapter| !chapter.hidden)
+		.map(|chapter| chapter.time_start)
+		.collect();
+	chapters.sort_unstable();
+	return chapters;
+}
+
+/// The files `split_title` creates for a title cut into `count` sections, in order
+pub fn part_paths(file: &Path, count: usize) -> anyhow::Result<Vec<PathBuf>> {
+	let stem = file
+		.file_stem()
+		.ok_or_else(|| anyhow!("{} is not a file", file.display()))?
+		.to_string_lossy();
+	return Ok((1..=count)
+		.map(|idx| file.with_file_name(format!("{stem} - part-{idx:03}.mkv")))
+		.collect());
+}
+
+/// Cuts sections out of a play-all title with mkvmerge, each running from its start
+/// to its end (or the end of the title), and returns the new files in the same order.
+/// Cuts can only be made on keyframes, so each file may start a moment early.
+pub async fn split_title(
+	file: &Path,
+	ranges: &[(Duration, Option<Duration>)],
+) -> anyhow::Result<Vec<PathBuf>> {
+	let parts: Vec<String> = ranges
+		.iter()
+		.map(|(start, end)| {
+			format!(
+				"{}-{}",
+				describe_timestamp(*start),
+				end.map(describe_timestamp).unwrap_or_default()
+			)
+		})
+		.collect();
+	let stem = file
+		.file_stem()
+		.ok_or_else(|| anyhow!("{} is not a file", file.display()))?
+		.to_string_lossy();
+	let output = file.with_file_name(format!("{stem} - part.mkv"));
+	let status = Command::new("mkvmerge")
+		.arg("--quiet")
+		.arg("--output")
+		.arg(&output)
+		.arg("--split")
+		.arg(format!("parts:{}", parts.join(",")))
+		.arg(file)
+		.status()
+		.await
+		.context("Couldn't run mkvmerge")?;
+	// mkvmerge exits with 1 when it only had warnings
+	if !matches!(status.code(), Some(0 | 1)) {
+		return Err(anyhow!("Failed to split {}", file.display()));
+	}
+	let files = part_paths(file, ranges.len())?;
+	for part in &files {
+		if !part.exists() {
+			return Err(anyhow!("mkvmerge didn't create {}", part.display()));
+		}
+	}
+	return Ok(files);
+}
+
+/// Formats a position within a title as `H:MM:SS.mmm`
+pub fn describe_timestamp(timestamp: Duration) -> String {
+	let seconds = timestamp.as_secs();
+	return format!(
+		"{}:{:02}:{:02}.{:03}",
+		seconds / 3600,
+		seconds / 60 % 60,
+		seconds % 60,
+		timestamp.subsec_millis()
+	);
+}
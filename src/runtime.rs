@@ -0,0 +1,154 @@
+use std::time::Duration;
+
+use tmdb_async::Episode;
+
+use crate::{assignment::assign, autotagger::describe_runtime};
+
+/// Titles shorter than this fraction of an episode's runtime can't be that episode
+const MIN_RUNTIME_RATIO: f64 = 0.67;
+
+/// Titles longer than this multiple of an episode's runtime can't be that episode
+const MAX_RUNTIME_RATIO: f64 = 1.5;
+
+/// How much the runtime counts for when there are no subtitles to go by, with the rest
+/// coming from the order of the titles on the disc
+const RUNTIME_WEIGHT: f64 = 0.5;
+
+/// Cost at which a title is left alone when matching by runtime and order
+const FALLBACK_UNASSIGNED_COST: f64 = 0.5;
+
+/// Matches made without subtitles are never more than this confident, so that they
+/// aren't accepted without asking alongside ones that were
+const MAX_FALLBACK_CONFIDENCE: f64 = 0.5;
+
+/// An episode's runtime as listed on TMDB, if it's known
+pub fn episode_runtime(episode: &Episode) -> Option<Duration> {
+	return match episode.runtime {
+		None | Some(0) => None,
+		Some(minutes) => Some(Duration::from_secs(minutes as u64 * 60)),
+	};
+}
+
+/// Combined runtime of several episodes, if all of them are known
+pub fn total_runtime(episodes: &[&Episode]) -> Option<Duration> {
+	return episodes
+		.iter()
+		.map(|episode| episode_runtime(episode))
+		.sum();
+}
+
+/// Whether a title could be an episode, going by their runtimes. Anything without a
+/// known runtime gets the benefit of the doubt.
+pub fn plausible_runtime(duration: Option<Duration>, runtime: Option<Duration>) -> bool {
+	let (Some(duration), Some(runtime)) = (duration, runtime) else {
+		return true;
+	};
+	let ratio = duration.as_secs_f64() / runtime.as_secs_f64();
+	return (MIN_RUNTIME_RATIO..=MAX_RUNTIME_RATIO).contains(&ratio);
+}
+
+pub fn describe_runtimes(duration: Option<Duration>, runtime: Option<Duration>) -> String {
+	let describe = |duration: Option<Duration>| {
+		duration.map_or_else(|| String::from("unknown"), describe_runtime)
+	};
+	let mut description = format!("{} (TMDB: {})", describe(duration), describe(runtime));
+	if !plausible_runtime(duration, runtime) {
+		description.push_str(", which doesn't fit");
+	}
+	return description;
+}
+
+/// A title matched by its runtime and position on the disc
+pub struct RuntimeMatch {
+	pub episode_idx: usize,
+	/// From 0 (perfect) to 1, like the cost of a subtitle match
+	pub cost: f64,
+	pub confidence: f64,
+}
+
+/// Matches titles to episodes without any subtitles, for discs that have none. Titles
+/// are expected to be in the same order as the episodes, and close to their runtime.
+///
+/// `durations` are the titles in disc order, and `runtimes` the episodes in episode
+/// order.
+pub fn match_by_runtime(
+	durations: &[Option<Duration>],
+	runtimes: &[Option<Duration>],
+) -> Vec<Option<RuntimeMatch>> {
+	// Only titles that could be an episode count towards the order, so extras and
+	// play-all titles don't push everything after them out of place
+	let candidates: Vec<usize> = (0..durations.len())
+		.filter(|file_idx| {
+			durations[*file_idx].is_some()
+				&& runtimes
+					.iter()
+					.any(|runtime| plausible_runtime(durations[*file_idx], *runtime))
+		})
+		.collect();
+	let position = |idx: usize, count: usize| -> f64 {
+		if count <= 1 {
+			return 0.0;
+		}
+		return idx as f64 / (count - 1) as f64;
+	};
+	let costs: Vec<Vec<f64>> = (0..durations.len())
+		.map(|file_idx| {
+			let order = candidates.iter().position(|idx| *idx == file_idx);
+			runtimes
+				.iter()
+				.enumerate()
+				.map(|(episode_idx, runtime)| {
+					let (Some(order), Some(duration), Some(runtime)) =
+						(order, durations[file_idx], runtime)
+					else {
+						return 1.0;
+					};
+					if !plausible_runtime(Some(duration), Some(*runtime)) {
+						return 1.0;
+					}
+					let runtime_cost = (duration.as_secs_f64() - runtime.as_secs_f64()).abs()
+						/ runtime.as_secs_f64();
+					let order_cost = (position(order, candidates.len())
+						- position(episode_idx, runtimes.len()))
+					.abs();
+					RUNTIME_WEIGHT * runtime_cost.min(1.0) + (1.0 - RUNTIME_WEIGHT) * order_cost
+				})
+				.collect()
+		})
+		.collect();
+
+	let assignments = assign(&costs, FALLBACK_UNASSIGNED_COST);
+	return assignments
+		.iter()
+		.enumerate()
+		.map(|(file_idx, assignment)| {
+			let episode_idx = (*assignment)?;
+			let cost = costs[file_idx][episode_idx];
+			// Measured against the next best episode for the title, or the next best title
+			// for the episode, whichever is closer
+			let runner_up = costs[file_idx]
+				.iter()
+				.enumerate()
+				.filter(|(idx, _)| *idx != episode_idx)
+				.map(|(_, cost)| *cost)
+				.chain(
+					costs
+						.iter()
+						.enumerate()
+						.filter(|(idx, _)| *idx != file_idx)
+						.map(|(_, row)| row[episode_idx]),
+				)
+				.fold(FALLBACK_UNASSIGNED_COST, f64::min);
+			let confidence = if runner_up <= 0.0 {
+				0.0
+			} else {
+				((runner_up - cost) / runner_up).clamp(0.0, 1.0) * MAX_FALLBACK_CONFIDENCE
+			};
+			Some(RuntimeMatch {
+				episode_idx,
+				cost,
+				confidence,
+			})
+		})
+		.collect();
+}
@@ -0,0 +1,182 @@
+use std::{
+	collections::{hash_map::DefaultHasher, HashMap},
+	hash::{Hash, Hasher},
+};
+
+use clap::ValueEnum;
+
+/// A cheap estimate of how alike two transcripts are. These are used to narrow down
+/// which pairs are worth the much more expensive edit distance.
+pub trait SimilarityEngine: Sync {
+	/// Scores every candidate against every reference from 0 (nothing in common)
+	/// to 1 (identical), indexed as `[candidate][reference]`.
+	fn similarities(&self, references: &[&str], candidates: &[&str]) -> Vec<Vec<f64>>;
+}
+
+/// Selects which similarity engine is used to prefilter candidates
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Prefilter {
+	/// Estimates the overlap of word pairs between transcripts
+	#[value(name = "minhash")]
+	MinHash,
+	/// Cosine similarity of word frequencies, weighted by how rare each word is
+	#[value(name = "tfidf")]
+	TfIdf,
+	/// Compares every file against every episode
+	None,
+}
+impl Prefilter {
+	pub fn engine(&self) -> Option<Box<dyn SimilarityEngine>> {
+		return match self {
+			Prefilter::MinHash => Some(Box::new(MinHashEngine::default())),
+			Prefilter::TfIdf => Some(Box::new(TfIdfEngine)),
+			Prefilter::None => None,
+		};
+	}
+}
+
+/// Picks the `count` most similar references for each candidate, best first
+pub fn shortlist(similarities: &[Vec<f64>], count: usize) -> Vec<Vec<usize>> {
+	return similarities
+		.iter()
+		.map(|row| {
+			let mut indexes: Vec<usize> = (0..row.len()).collect();
+			indexes.sort_unstable_by(|a, b| row[*b].total_cmp(&row[*a]));
+			indexes.truncate(count);
+			indexes
+		})
+		.collect();
+}
+
+/// Estimates the Jaccard similarity of the word shingles in each transcript using
+/// MinHash signatures, so each document only needs to be hashed once.
+pub struct MinHashEngine {
+	/// Number of consecutive words in each shingle
+	pub shingle_size: usize,
+	/// Number of hash functions in each signature. More is slower but more accurate.
+	pub signature_size: usize,
+}
+impl Default for MinHashEngine {
+	fn default() -> Self {
+		return Self {
+			shingle_size: 2,
+			signature_size: 128,
+		};
+	}
+}
+impl MinHashEngine {
+	fn signature(&self, text: &str) -> Vec<u64> {
+		let words: Vec<&str> = text.split_whitespace().collect();
+		let mut signature = vec![u64::MAX; self.signature_size];
+		for shingle in words.windows(self.shingle_size.min(words.len()).max(1)) {
+			let mut hasher = DefaultHasher::new();
+			shingle.hash(&mut hasher);
+			let shingle_hash = hasher.finish();
+			for (seed, slot) in signature.iter_mut().enumerate() {
+				let hash = mix(shingle_hash ^ mix(seed as u64 + 1));
+				if hash < *slot {
+					*slot = hash;
+				}
+			}
+		}
+		return signature;
+	}
+}
+impl SimilarityEngine for MinHashEngine {
+	fn similarities(&self, references: &[&str], candidates: &[&str]) -> Vec<Vec<f64>> {
+		let references: Vec<Vec<u64>> =
+			references.iter().map(|text| self.signature(text)).collect();
+		return candidates
+			.iter()
+			.map(|text| {
+				let candidate = self.signature(text);
+				references
+					.iter()
+					.map(|reference| {
+						let equal = reference
+							.iter()
+							.zip(&candidate)
+							.filter(|(a, b)| a == b && **a != u64::MAX)
+							.count();
+						equal as f64 / self.signature_size as f64
+					})
+					.collect()
+			})
+			.collect();
+	}
+}
+
+/// splitmix64 finalizer, used to derive independent hash functions from one hash
+fn mix(mut value: u64) -> u64 {
+	value = (value ^ (value >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+	value = (value ^ (value >> 27)).wrapping_mul(0x94d049bb133111eb);
+	return value ^ (value >> 31);
+}
+
+/// Cosine similarity between TF-IDF weighted word vectors. Words that appear in
+/// every transcript (names of main characters, for example) count for very little.
+pub struct TfIdfEngine;
+impl SimilarityEngine for TfIdfEngine {
+	fn similarities(&self, references: &[&str], candidates: &[&str]) -> Vec<Vec<f64>> {
+		let term_counts = |text: &str| -> HashMap<String, f64> {
+			let mut counts = HashMap::<String, f64>::new();
+			for word in text.split_whitespace() {
+				*counts.entry(word.to_lowercase()).or_default() += 1.0;
+			}
+			return counts;
+		};
+		let references: Vec<HashMap<String, f64>> =
+			references.iter().map(|text| term_counts(text)).collect();
+		let candidates: Vec<HashMap<String, f64>> =
+			candidates.iter().map(|text| term_counts(text)).collect();
+
+		// Document frequency across everything we know about
+		let mut document_frequency = HashMap::<&str, f64>::new();
+		for document in references.iter().chain(&candidates) {
+			for term in document.keys() {
+				*document_frequency.entry(term).or_default() += 1.0;
+			}
+		}
+		let document_count = (references.len() + candidates.len()) as f64;
+		let weigh = |document| weigh_terms(document, &document_frequency, document_count);
+		let references: Vec<_> = references.iter().map(weigh).collect();
+
+		return candidates
+			.iter()
+			.map(|candidate| {
+				let (candidate, candidate_norm) = weigh(candidate);
+				references
+					.iter()
+					.map(|(reference, reference_norm)| {
+						if candidate_norm == 0.0 || *reference_norm == 0.0 {
+							return 0.0;
+						}
+						let dot: f64 = candidate
+							.iter()
+							.filter_map(|(term, weight)| Some(weight * reference.get(term)?))
+							.sum();
+						dot / (candidate_norm * reference_norm)
+					})
+					.collect()
+			})
+			.collect();
+	}
+}
+
+/// Turns term counts into TF-IDF weights, also returning the length of the vector
+fn weigh_terms<'a>(
+	document: &'a HashMap<String, f64>,
+	document_frequency: &HashMap<&str, f64>,
+	document_count: f64,
+) -> (HashMap<&'a str, f64>, f64) {
+	let mut weights = HashMap::<&str, f64>::new();
+	let mut norm = 0.0;
+	for (term, count) in document {
+		let idf = (document_count / document_frequency[term.as_str()]).ln() + 1.0;
+		// Sublinear term frequency keeps a handful of filler words from dominating
+		let weight = (1.0 + count.ln()) * idf;
+		norm += weight * weight;
+		weights.insert(term, weight);
+	}
+	return (weights, norm.sqrt());
+}
@@ -0,0 +1,16 @@
+/// Deterministic xorshift generator, so that tests and benchmarks see the same input on
+/// every run
+pub struct Rng(pub u64);
+
+impl Rng {
+	pub fn next(&mut self) -> u64 {
+		self.0 ^= self.0 << 13;
+		self.0 ^= self.0 >> 7;
+		self.0 ^= self.0 << 17;
+		return self.0;
+	}
+
+	pub fn below(&mut self, limit: usize) -> usize {
+		return (self.next() % limit as u64) as usize;
+	}
+}
@@ -1,7 +1,7 @@
 use isocountry::CountryCode;
 use isolanguage_1::LanguageCode;
 
-use crate::Client;
+use crate::{Client, EpisodeGroupType};
 
 const API_KEY: &str = env!("TMDB_API_KEY");
 
@@ -189,3 +189,18 @@ async fn tv_external_ids() {
 	assert_eq!(ids.twitter_id(), Some("HaloTheSeries".into()));
 }
 
+
+#[tokio::test]
+async fn tv_episode_groups() {
+	let client = client();
+	let groups = client.tv_episode_groups(1437).await.unwrap();
+	let dvd_order = groups.iter().find(|group| group.r#type() == EpisodeGroupType::Dvd).unwrap();
+
+	let group = client.episode_group(dvd_order.id()).await.unwrap();
+	assert_eq!(group.id(), dvd_order.id());
+	assert_eq!(group.r#type(), EpisodeGroupType::Dvd);
+	assert_eq!(group.groups().len() as u32, group.group_count());
+	let episodes: usize = group.groups().iter().map(|subgroup| subgroup.episodes().len()).sum();
+	assert_eq!(episodes as u32, group.episode_count());
+	assert_eq!("Serenity", group.groups()[0].episodes()[0].episode().name);
+}
@@ -5,13 +5,15 @@ use compact_str::format_compact;
 use compact_str::CompactString;
 use compact_str::ToCompactString;
 use itertools::Itertools;
-use model::TVSeason;
 pub use reqwest::Error;
 use serde::de::DeserializeOwned;
 
 mod model;
-use model::FindResult;
-pub use model::{Episode, Movie, MovieSearchResult, TVExternalIds, TVSearchResult, TV};
+use model::{FindResult, Results};
+pub use model::{
+	Episode, EpisodeGroup, EpisodeGroupSummary, EpisodeGroupType, EpisodeSubgroup, GroupedEpisode, Movie, MovieSearchResult,
+	TVExternalIds, TVSearchResult, TVSeason, TV
+};
 
 #[cfg(test)]
 mod integration_tests;
@@ -141,6 +143,19 @@ impl Client {
 		self.tv_by_id(result.tv_results()[0].id(), false, false).await
 	}
 
+	#[inline]
+	pub async fn tv_episode_groups(&self, id: u32) -> Result<Vec<EpisodeGroupSummary>, Error> {
+		let path = format_compact!("/tv/{}/episode_groups", id);
+		let result: Results<EpisodeGroupSummary> = self.get(&path, &[]).await?;
+		Ok(result.results().to_vec())
+	}
+
+	#[inline]
+	pub async fn episode_group(&self, id: &str) -> Result<EpisodeGroup, Error> {
+		let path = format_compact!("/tv/episode_group/{}", id);
+		self.get(&path, &[]).await
+	}
+
 	#[inline]
 	pub async fn tv_external_ids(&self, id: u32) -> Result<TVExternalIds, Error> {
 		let path = format_compact!("/tv/{}/external_ids", id);
@@ -32,6 +32,12 @@ mod optional_date {
 			}
 			return Ok(Some(Date::parse(v, &DATE_FORMAT).map_err(|err| E::custom(err))?));
 		}
+		fn visit_unit<E>(self) -> Result<Self::Value, E>
+		where
+			E: serde::de::Error,
+		{
+			return Ok(None);
+		}
 	}
 
 	pub fn serialize<S>(obj: &Option<Date>, serializer: S) -> Result<S::Ok, S::Error>
@@ -636,3 +642,112 @@ impl TVExternalIds {
 		self.twitter_id.as_deref()
 	}
 }
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Deserialize)]
+#[serde(from = "u8")]
+pub enum EpisodeGroupType {
+	OriginalAirDate,
+	Absolute,
+	Dvd,
+	Digital,
+	StoryArc,
+	Production,
+	Tv,
+	Unknown(u8),
+}
+
+impl From<u8> for EpisodeGroupType {
+	fn from(value: u8) -> Self {
+		match value {
+			1 => Self::OriginalAirDate,
+			2 => Self::Absolute,
+			3 => Self::Dvd,
+			4 => Self::Digital,
+			5 => Self::StoryArc,
+			6 => Self::Production,
+			7 => Self::Tv,
+			other => Self::Unknown(other),
+		}
+	}
+}
+
+impl std::fmt::Display for EpisodeGroupType {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Self::OriginalAirDate => write!(f, "Original air date"),
+			Self::Absolute => write!(f, "Absolute"),
+			Self::Dvd => write!(f, "DVD"),
+			Self::Digital => write!(f, "Digital"),
+			Self::StoryArc => write!(f, "Story arc"),
+			Self::Production => write!(f, "Production"),
+			Self::Tv => write!(f, "TV"),
+			Self::Unknown(other) => write!(f, "Unknown ({other})"),
+		}
+	}
+}
+
+/// An alternate ordering of a show's episodes, as listed by `/tv/{id}/episode_groups`
+#[derive(Debug, Clone, PartialEq, Deserialize, Getset)]
+pub struct EpisodeGroupSummary {
+	#[getset(deref_get, vis = "pub")]
+	id: CompactString,
+	#[getset(deref_get, vis = "pub")]
+	name: CompactString,
+	#[getset(deref_get, vis = "pub")]
+	description: String,
+	#[getset(get_copy, vis = "pub")]
+	episode_count: u32,
+	#[getset(get_copy, vis = "pub")]
+	group_count: u32,
+	#[getset(get_copy, vis = "pub")]
+	r#type: EpisodeGroupType,
+	#[getset(as_ref_get, vis = "pub", type = "Option<&Network>")]
+	network: Option<Network>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Getset)]
+pub struct EpisodeGroup {
+	#[getset(deref_get, vis = "pub")]
+	id: CompactString,
+	#[getset(deref_get, vis = "pub")]
+	name: CompactString,
+	#[getset(deref_get, vis = "pub")]
+	description: String,
+	#[getset(get_copy, vis = "pub")]
+	episode_count: u32,
+	#[getset(get_copy, vis = "pub")]
+	group_count: u32,
+	#[getset(get_copy, vis = "pub")]
+	r#type: EpisodeGroupType,
+	#[getset(as_ref_get, vis = "pub", type = "Option<&Network>")]
+	network: Option<Network>,
+	#[getset(deref_get, vis = "pub")]
+	groups: Vec<EpisodeSubgroup>,
+}
+
+/// One of the groups within an episode group, usually standing in for a season
+#[derive(Debug, Clone, PartialEq, Deserialize, Getset)]
+pub struct EpisodeSubgroup {
+	#[getset(deref_get, vis = "pub")]
+	id: CompactString,
+	#[getset(deref_get, vis = "pub")]
+	name: CompactString,
+	#[getset(get_copy, vis = "pub")]
+	order: u32,
+	#[getset(get_copy, vis = "pub")]
+	locked: bool,
+	#[getset(deref_get, vis = "pub")]
+	episodes: Vec<GroupedEpisode>,
+}
+
+/// An episode along with its position in an episode group. The episode itself keeps
+/// its aired season and episode numbers.
+#[derive(Debug, Clone, PartialEq, Deserialize, Getset)]
+pub struct GroupedEpisode {
+	#[serde(flatten)]
+	#[getset(get, vis = "pub")]
+	episode: Episode,
+	/// Position of the episode within its subgroup, starting from 0
+	#[getset(get_copy, vis = "pub")]
+	order: u32,
+}